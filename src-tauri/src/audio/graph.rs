@@ -1,17 +1,20 @@
 use itertools::{Either, Itertools};
-use ndarray::{Array2, Zip};
+use ndarray::{s, Array2};
 use serde::{Deserialize, Serialize};
+use strum::{EnumIter, IntoEnumIterator};
 
-use std::{collections::HashMap, thread::sleep, time::Duration};
+use std::{fmt::Display, str::FromStr, time::Duration};
 
-use crate::audio::low_level::AudioWave;
+use crate::state::settings::AudioIndicator;
 
-use super::{low_level::Playable, Waveform};
-
-pub fn play_rasta(vals: RasterGraphCreationData) {
-    let rasta_graph = RasterGraph::new(vals);
-    rasta_graph.play();
-}
+use super::{
+    indicators::{different_wave, Indicators},
+    legend::{describe_indicator, format_value, Legend, REFERENCE_POINTS},
+    low_level::Playable,
+    mapping::PitchMapping,
+    score::{position, Score, Tone},
+    Waveform,
+};
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
 pub struct RasterGraphSettings {
@@ -22,9 +25,213 @@ pub struct RasterGraphSettings {
     pub rows: usize,
     pub cols: usize,
     pub classified: bool,
+    /// Plays each class with its own waveform at a fixed pitch instead of mapping classes to pitches
+    /// A legend of the sound for each class is played first
+    #[serde(default)]
+    pub categorical: bool,
     pub wave: Waveform,
     pub min_value: Option<f64>,
     pub max_value: Option<f64>,
+    #[serde(default)]
+    pub mapping: PitchMapping,
+    #[serde(default)]
+    pub scan_order: ScanOrder,
+    /// How the pixels in each cell are combined, classified graphs always use the most common value
+    #[serde(default)]
+    pub aggregation: Aggregation,
+    /// Fills cells without any data from their neighbours instead of playing the no data indicator
+    #[serde(default)]
+    pub fill_gaps: bool,
+    /// Plays reference tones for the lowest, highest and some values in between and the no data indicator before the graph
+    #[serde(default)]
+    pub legend: bool,
+}
+
+/// How the pixels that fall within a single cell of a graph are combined into one value
+#[derive(
+    Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize, specta::Type, EnumIter,
+)]
+pub enum Aggregation {
+    #[default]
+    Mean,
+    Median,
+    Min,
+    Max,
+    /// The most common value
+    Mode,
+    /// The value below which the given percentage of pixels fall
+    Percentile(f64),
+}
+
+impl Aggregation {
+    /// Combines the values of a cell, returns None if there are no values
+    /// The values may be reordered
+    pub fn apply(self, values: &mut [f64]) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+        match self {
+            Self::Mean => Some(values.iter().sum::<f64>() / values.len() as f64),
+            Self::Median => Self::Percentile(50.0).apply(values),
+            Self::Min => values.iter().copied().reduce(f64::min),
+            Self::Max => values.iter().copied().reduce(f64::max),
+            Self::Mode => {
+                values.sort_by(f64::total_cmp);
+                values
+                    .iter()
+                    .dedup_with_count()
+                    .max_by_key(|(count, _)| *count)
+                    .map(|(_, value)| *value)
+            }
+            Self::Percentile(percentile) => {
+                values.sort_by(f64::total_cmp);
+                // Interpolates between the two closest ranks
+                let rank = (percentile.clamp(0.0, 100.0) / 100.0) * (values.len() - 1) as f64;
+                let lower = values[rank.floor() as usize];
+                let upper = values[rank.ceil() as usize];
+                Some(lower + (upper - lower) * rank.fract())
+            }
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mean" => Ok(Self::Mean),
+            "median" => Ok(Self::Median),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            "mode" => Ok(Self::Mode),
+            other => other
+                .strip_prefix("percentile")
+                .or_else(|| other.strip_prefix('p'))
+                .and_then(|percentile| percentile.trim_start_matches([':', '=']).parse().ok())
+                .filter(|percentile| (0.0..=100.0).contains(percentile))
+                .map(Self::Percentile)
+                .ok_or("Not a valid aggregation, use mean, median, min, max, mode or a percentile such as p90"),
+        }
+    }
+}
+
+impl Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mean => f.write_str("mean"),
+            Self::Median => f.write_str("median"),
+            Self::Min => f.write_str("min"),
+            Self::Max => f.write_str("max"),
+            Self::Mode => f.write_str("mode"),
+            Self::Percentile(percentile) => write!(f, "p{percentile}"),
+        }
+    }
+}
+
+/// The order the cells of a graph are played in
+#[derive(
+    Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize, specta::Type, EnumIter,
+)]
+pub enum ScanOrder {
+    /// Each row from left to right, starting from the top
+    #[default]
+    Rows,
+    /// Alternates between left to right and right to left so there is no jump between rows
+    Boustrophedon,
+    /// Each column from top to bottom, starting from the left
+    Columns,
+    /// Spirals out from the centre one ring at a time
+    Spiral,
+}
+
+impl ScanOrder {
+    /// Splits the cells of a grid into the lines they are played in
+    /// Each line is played with a border sound at either end and can be skipped to during playback
+    pub fn lines(self, rows: usize, cols: usize) -> Vec<Vec<(usize, usize)>> {
+        match self {
+            Self::Rows => (0..rows)
+                .map(|row| (0..cols).map(|col| (row, col)).collect())
+                .collect(),
+            Self::Boustrophedon => (0..rows)
+                .map(|row| {
+                    let line = (0..cols).map(|col| (row, col));
+                    if row % 2 == 0 {
+                        line.collect()
+                    } else {
+                        line.rev().collect()
+                    }
+                })
+                .collect(),
+            Self::Columns => (0..cols)
+                .map(|col| (0..rows).map(|row| (row, col)).collect())
+                .collect(),
+            Self::Spiral => spiral(rows, cols),
+        }
+    }
+}
+
+/// Walks outwards from the centre of the grid in a square spiral
+/// Cells are grouped into rings by how far they are from the centre
+fn spiral(rows: usize, cols: usize) -> Vec<Vec<(usize, usize)>> {
+    let total = rows * cols;
+    let centre = ((rows / 2) as isize, (cols / 2) as isize);
+    let (mut row, mut col) = centre;
+    let directions = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+    let mut rings: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut visited = 0;
+    let mut step_len = 1;
+    let mut direction = 0;
+    // Every cell the walk passes through, including ones outside of the grid
+    let mut path = vec![(row, col)];
+    while visited < total {
+        for (row, col) in path.drain(..) {
+            if row < 0 || col < 0 || row as usize >= rows || col as usize >= cols {
+                continue;
+            }
+            let ring = (row - centre.0).abs().max((col - centre.1).abs()) as usize;
+            if rings.len() <= ring {
+                rings.resize_with(ring + 1, Vec::new);
+            }
+            rings[ring].push((row as usize, col as usize));
+            visited += 1;
+        }
+        // The spiral takes two turns for each increase in length
+        for _ in 0..2 {
+            let (d_row, d_col) = directions[direction % 4];
+            for _ in 0..step_len {
+                row += d_row;
+                col += d_col;
+                path.push((row, col));
+            }
+            direction += 1;
+        }
+        step_len += 1;
+    }
+    rings
+}
+
+impl FromStr for ScanOrder {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rows" => Ok(Self::Rows),
+            "boustrophedon" => Ok(Self::Boustrophedon),
+            "columns" => Ok(Self::Columns),
+            "spiral" => Ok(Self::Spiral),
+            _ => Err("Not a valid scan order"),
+        }
+    }
+}
+
+impl Display for ScanOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rows => f.write_str("rows"),
+            Self::Boustrophedon => f.write_str("boustrophedon"),
+            Self::Columns => f.write_str("columns"),
+            Self::Spiral => f.write_str("spiral"),
+        }
+    }
 }
 
 fn count_categories(data: &Array2<f64>, no_data_value: Option<f64>) -> Vec<f64> {
@@ -53,9 +260,15 @@ impl Default for RasterGraphSettings {
             rows: 10,
             cols: 10,
             classified: false,
+            categorical: false,
             wave: Waveform::default(),
             min_value: None,
             max_value: None,
+            mapping: PitchMapping::default(),
+            scan_order: ScanOrder::default(),
+            aggregation: Aggregation::default(),
+            fill_gaps: false,
+            legend: false,
         }
     }
 }
@@ -63,6 +276,7 @@ impl Default for RasterGraphSettings {
 #[derive(Clone, Debug)]
 pub struct RasterGraph {
     inner: Vec<RasterGraphInner>,
+    indicators: Indicators,
 }
 
 #[derive(Clone, Debug)]
@@ -73,74 +287,268 @@ struct RasterGraphInner {
 }
 
 impl RasterGraphInner {
-    fn calculate_mean(&self) -> Array2<Option<f64>> {
-        let x_scale = self.data.ncols() / self.settings.cols;
-        let y_scale = self.data.nrows() / self.settings.rows;
-        Zip::from(self.data.exact_chunks((y_scale, x_scale))).map_collect(|chunk| {
-            let cleaned = if let Some(ref no_data_value) = self.no_data_value {
-                Either::Left(
-                    chunk
-                        .into_iter()
-                        .filter(move |x| x.is_finite() && *x != no_data_value),
-                )
-            } else {
-                Either::Right(chunk.into_iter().filter(|x| x.is_finite()))
-            };
-            if cleaned.clone().count() == 0 {
-                None
-            } else {
-                Some(cleaned.sum::<f64>() / (y_scale * x_scale) as f64)
-            }
+    /// Splits the raster into the cells of the graph and combines the pixels of each cell into a single value
+    /// When the raster doesn't divide evenly the extra pixels are spread across the cells rather than dropped
+    fn aggregate(&self, aggregation: Aggregation) -> Array2<Option<f64>> {
+        let (rows, cols) = (self.settings.rows.max(1), self.settings.cols.max(1));
+        let (data_rows, data_cols) = self.data.dim();
+        Array2::from_shape_fn((rows, cols), |(row, col)| {
+            let (row_start, row_end) = cell_bounds(row, rows, data_rows);
+            let (col_start, col_end) = cell_bounds(col, cols, data_cols);
+            let mut values = self
+                .data
+                .slice(s![row_start..row_end, col_start..col_end])
+                .iter()
+                .filter(|x| x.is_finite() && Some(**x) != self.no_data_value)
+                .copied()
+                .collect_vec();
+            aggregation.apply(&mut values)
         })
     }
+}
 
-    /// Counts each pixel in each cell and returns the most common one for each cell
-    /// Note this isn't very clever so if there's 20 different values then 5% being the same in a cell is enough to return it as the most common, first past the post style
-    ///If every cell happens to have the same 5% majority then the whole image will be interpreted as that value.
-    fn calculate_max_count(&self) -> Array2<Option<f64>> {
-        let x_scale = self.data.ncols() / self.settings.cols;
-        let y_scale = self.data.nrows() / self.settings.rows;
-        if let Some(no_data_value) = &self.no_data_value {
-            Zip::from(self.data.exact_chunks((y_scale, x_scale))).map_collect(|chunk| {
-                let mut counts = HashMap::<u64, usize>::new();
-                for x in chunk
-                    .into_iter()
-                    .filter(|x| x.is_finite() && *x != no_data_value)
-                {
-                    counts
-                        .entry(x.to_bits())
-                        .and_modify(|count| *count += 1)
-                        .or_insert(1);
+/// The range of pixels that fall in a cell along one axis
+/// If there are more cells than pixels each cell takes the nearest pixel
+fn cell_bounds(cell: usize, cells: usize, pixels: usize) -> (usize, usize) {
+    let start = (cell * pixels / cells).min(pixels.saturating_sub(1));
+    let end = ((cell + 1) * pixels / cells).max(start + 1).min(pixels);
+    (start, end)
+}
+
+type RasterGraphCreationData = Vec<(Array2<f64>, f64, f64, Option<f64>, RasterGraphSettings)>;
+
+/// The sound a class of a categorical graph is played with
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ClassSound {
+    wave: Waveform,
+    freq: f64,
+    /// How many octaves the class is shifted by once every waveform has been used
+    octave: i32,
+}
+
+impl ClassSound {
+    /// Gives each class its own waveform at a fixed pitch in the middle of the frequency range
+    /// When there are more classes than waveforms the waveforms are reused an octave up, then an octave down and so on
+    fn for_classes(classes: usize, min_freq: f64, max_freq: f64) -> Vec<Self> {
+        let waves = Waveform::iter().collect_vec();
+        let middle = (min_freq * max_freq).sqrt();
+        (0..classes)
+            .map(|class| {
+                let cycle = (class / waves.len()) as i32;
+                let octave = if cycle % 2 == 1 {
+                    cycle / 2 + 1
+                } else {
+                    -(cycle / 2)
+                };
+                Self {
+                    wave: waves[class % waves.len()],
+                    freq: middle * 2_f64.powi(octave),
+                    octave,
                 }
-                counts
-                    .into_iter()
-                    .sorted_by_key(|x| x.1)
-                    .last()
-                    .map(|x| f64::from_bits(x.0))
             })
+            .collect()
+    }
+
+    fn describe(&self, value: f64) -> String {
+        let register = match self.octave {
+            0 => String::new(),
+            1 => " an octave up".to_string(),
+            -1 => " an octave down".to_string(),
+            octave if octave > 0 => format!(" {octave} octaves up"),
+            octave => format!(" {} octaves down", -octave),
+        };
+        format!("{value} is {}{register}", self.wave.description())
+    }
+}
+
+/// A graph reduced to the cells that will actually be played
+struct GraphCells {
+    /// The waveform of each voice this graph uses
+    voices: Vec<Waveform>,
+    /// The sound of each class for categorical graphs, the cells hold the index of the class
+    class_sounds: Option<Vec<ClassSound>>,
+    cells: Array2<Option<f64>>,
+    min: f64,
+    max: f64,
+    min_freq: f64,
+    max_freq: f64,
+    mapping: PitchMapping,
+    /// How long each line of cells takes to play, whichever way the grid is scanned
+    row_duration: Duration,
+}
+
+impl GraphCells {
+    fn freq(&self, value: f64) -> f64 {
+        let y_range = self.max - self.min;
+        let y_range = if y_range == 0.0 { 1.0 } else { y_range };
+        self.mapping
+            .freq((value - self.min) / y_range, self.min_freq, self.max_freq)
+    }
+
+    fn value(&self, row: usize, col: usize) -> Option<f64> {
+        self.cells.get((row, col)).copied().flatten()
+    }
+
+    /// The tone for each of this graphs voices when playing a value
+    fn tones(&self, value: f64, pos: f64, volume: f64) -> Vec<Tone> {
+        match &self.class_sounds {
+            Some(sounds) => {
+                let sound = sounds.get(value as usize);
+                self.voices
+                    .iter()
+                    .map(|wave| match sound {
+                        Some(sound) if sound.wave == *wave => Tone::new(sound.freq, pos, volume),
+                        _ => Tone::silent(pos),
+                    })
+                    .collect()
+            }
+            None => vec![Tone::new(self.freq(value), pos, volume)],
+        }
+    }
+}
+
+impl RasterGraphInner {
+    fn is_categorical(&self) -> bool {
+        self.settings.classified || self.settings.categorical
+    }
+
+    /// Every distinct value in the raster, used as the classes of classified and categorical graphs
+    fn classes(&self) -> Vec<f64> {
+        count_categories(&self.data, self.no_data_value)
+    }
+
+    fn class_sounds(&self, classes: &[f64]) -> Option<Vec<ClassSound>> {
+        self.settings.categorical.then(|| {
+            ClassSound::for_classes(
+                classes.len(),
+                self.settings.min_freq,
+                self.settings.max_freq,
+            )
+        })
+    }
+
+    /// The values that map to the minimum and maximum frequencies
+    /// For classified graphs these are indexes into the classes
+    fn range(&self, classes: &[f64]) -> (f64, f64) {
+        if self.is_categorical() {
+            (
+                self.settings.min_value.unwrap_or(0.0),
+                self.settings
+                    .max_value
+                    .unwrap_or(classes.len().saturating_sub(1) as f64),
+            )
         } else {
-            Zip::from(self.data.exact_chunks((y_scale, x_scale))).map_collect(|chunk| {
-                let mut counts = HashMap::<u64, usize>::new();
-                for x in chunk.into_iter().filter(|x| x.is_finite()) {
-                    counts
-                        .entry(x.to_bits())
-                        .and_modify(|count| *count += 1)
-                        .or_insert(1);
-                }
-                counts
-                    .into_iter()
-                    .sorted_by_key(|x| x.1)
-                    .last()
-                    .map(|x| f64::from_bits(x.0))
+            let valid = self
+                .data
+                .iter()
+                .filter(|x| x.is_finite() && Some(**x) != self.no_data_value);
+            let min = *valid
+                .clone()
+                .min_by(|a, b| a.partial_cmp(b).unwrap())
+                .unwrap();
+            let max = *valid.max_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
+            (min, max)
+        }
+    }
+
+    /// The values played in the legend and a description of each
+    fn reference_values(&self) -> Vec<(f64, String)> {
+        if !self.settings.legend || self.settings.categorical {
+            return Vec::new();
+        }
+        let classes = if self.is_categorical() {
+            self.classes()
+        } else {
+            Vec::new()
+        };
+        let (min, max) = self.range(&classes);
+        REFERENCE_POINTS
+            .iter()
+            .map(|(fraction, name)| {
+                let value = min + (max - min) * fraction;
+                let shown = if self.is_categorical() {
+                    classes
+                        .get(value.round() as usize)
+                        .copied()
+                        .unwrap_or(value)
+                } else {
+                    value
+                };
+                (value, format!("{name}, {}", format_value(shown)))
             })
+            .collect()
+    }
+
+    fn cells(&self) -> GraphCells {
+        let RasterGraphSettings {
+            row_duration,
+            min_freq,
+            max_freq,
+            classified: _,
+            categorical: _,
+            wave,
+            rows: _,
+            cols: _,
+            min_value: _,
+            max_value: _,
+            mapping,
+            scan_order: _,
+            aggregation,
+            fill_gaps,
+            legend: _,
+        } = self.settings;
+        let mut class_sounds = None;
+        let (cells, min, max) = if self.is_categorical() {
+            let categories = self.classes();
+            class_sounds = self.class_sounds(&categories);
+            let (min, max) = self.range(&categories);
+            let data = self.data.map(|val| {
+                categories
+                    .iter()
+                    .enumerate()
+                    .find(|category| val == category.1)
+                    .map(|x| x.0 as f64)
+                    .unwrap_or(f64::NAN)
+            });
+            let self_with_data = RasterGraphInner {
+                data,
+                // Class indexes could clash with the no data value so missing values are stored as NaN instead
+                no_data_value: None,
+                ..self.clone()
+            };
+            (self_with_data.aggregate(Aggregation::Mode), min, max)
+        } else {
+            let (min, max) = self.range(&[]);
+            let cells = self.aggregate(aggregation);
+            let cells = if fill_gaps {
+                fill_gaps_in(&cells)
+            } else {
+                cells
+            };
+            (cells, min, max)
+        };
+        let voices = match &class_sounds {
+            Some(sounds) if !sounds.is_empty() => {
+                sounds.iter().map(|sound| sound.wave).unique().collect()
+            }
+            _ => vec![wave],
+        };
+        GraphCells {
+            voices,
+            class_sounds,
+            cells,
+            min,
+            max,
+            min_freq,
+            max_freq,
+            mapping,
+            row_duration,
         }
     }
 }
 
-type RasterGraphCreationData = Vec<(Array2<f64>, f64, f64, Option<f64>, RasterGraphSettings)>;
-
 impl RasterGraph {
-    pub fn new(vals: RasterGraphCreationData) -> Self {
+    pub fn new(vals: RasterGraphCreationData, indicators: Indicators) -> Self {
         Self {
             inner: vals
                 .into_iter()
@@ -150,148 +558,201 @@ impl RasterGraph {
                     settings,
                 })
                 .collect(),
+            indicators,
         }
     }
-}
 
-impl Playable for RasterGraph {
-    fn run<T>(&self, device: &cpal::Device, config: &cpal::StreamConfig)
-    where
-        T: cpal::Sample + cpal::SizedSample + cpal::FromSample<f64>,
-    {
-        let everything = self
+    /// Prefixes a legend label with the graph it is for when several are played at once
+    fn label(&self, graph: usize, label: String) -> String {
+        if self.inner.len() > 1 {
+            format!("Graph {}, {label}", graph + 1)
+        } else {
+            label
+        }
+    }
+
+    /// A description of each sound of the legend played before the graph
+    /// This is cheaper than building the whole score so can be sent back to the UI straight away
+    pub fn legend_text(&self) -> Vec<String> {
+        let mut text = self
             .inner
             .iter()
-            .map(|graph| {
-                let RasterGraphSettings {
-                    row_duration,
-                    min_freq,
-                    max_freq,
-                    classified,
-                    wave,
-                    rows: _,
-                    cols: _,
-                    min_value,
-                    max_value,
-                } = graph.settings;
-                let (data, min, max) = if classified {
-                    let categories = count_categories(&graph.data, graph.no_data_value);
-                    let data = graph.data.map(|val| {
-                        categories
-                            .iter()
-                            .enumerate()
-                            .find(|category| val == category.1)
-                            .map(|x| x.0 as f64)
-                            .or(graph.no_data_value)
-                            .unwrap_or(f64::NAN)
-                    });
-                    let min = match min_value {
-                        Some(min) => min,
-                        None => *data
-                            .iter()
-                            .filter(|x| x.is_finite() && Some(**x) != graph.no_data_value)
-                            .min_by(|a, b| a.partial_cmp(b).unwrap())
-                            .unwrap(),
-                    };
-                    let max = match max_value {
-                        Some(min) => min,
-                        None => *data
-                            .iter()
-                            .filter(|x| x.is_finite() && Some(**x) != graph.no_data_value)
-                            .max_by(|a, b| a.partial_cmp(b).unwrap())
-                            .unwrap(),
-                    };
-                    let self_with_data = RasterGraphInner {
-                        data,
-                        ..graph.clone()
-                    };
-                    (self_with_data.calculate_max_count(), min, max)
-                } else {
-                    let min = *graph
-                        .data
-                        .iter()
-                        .filter(|x| x.is_finite() && Some(**x) != graph.no_data_value)
-                        .min_by(|a, b| a.partial_cmp(b).unwrap())
-                        .unwrap();
-                    let max = *graph
-                        .data
-                        .iter()
-                        .filter(|x| x.is_finite() && Some(**x) != graph.no_data_value)
-                        .max_by(|a, b| a.partial_cmp(b).unwrap())
-                        .unwrap();
-                    (graph.calculate_mean(), min, max)
-                };
-                dbg!(min);
-                dbg!(max);
-
-                let row_len = data.ncols() as f64;
-                let duration_per_sample_ms = row_duration.div_f64(row_len);
-                let y_range = max - min;
-                let y_range = if y_range == 0.0 { 1.0 } else { y_range };
-                let freq_range = max_freq - min_freq;
-                // ... generate sound signal based on self.y and other parameters
-
-                let wave = AudioWave::new::<T>(wave, device, config);
-                let _pos_f = -1.0;
-                (
-                    wave,
-                    data,
-                    (
-                        min,
-                        min_freq,
-                        y_range,
-                        freq_range,
-                        row_len,
-                        duration_per_sample_ms,
-                    ),
-                )
+            .enumerate()
+            .flat_map(|(index, graph)| {
+                let classes = graph.classes();
+                let sounds = graph.class_sounds(&classes).unwrap_or_default();
+                let class_labels = classes
+                    .into_iter()
+                    .zip(sounds)
+                    .map(|(value, sound)| sound.describe(value));
+                let reference_labels = graph.reference_values().into_iter().map(|(_, label)| label);
+                class_labels
+                    .chain(reference_labels)
+                    .map(|label| self.label(index, label))
+                    .collect_vec()
             })
-            .collect::<Vec<_>>();
-        let row_count = everything[0].1.nrows();
-        let col_count = everything[0].1.ncols();
-        let duration_per_sample_ms = everything[0].2 .5;
-        for row in 0..row_count {
-            for i in 0..col_count {
-                for (wave, data, (min, min_freq, y_range, freq_range, row_len, _)) in
-                    everything.iter()
-                {
-                    let row = data.row(row);
-                    let pixel = row[i];
-                    let freq_f = if let Some(pixel) = pixel {
-                        (pixel - min) / y_range * freq_range + min_freq
-                    } else {
-                        0.
-                    };
-                    let pos_f = i as f64 / (row_len - 1.) * 2.0 - 1.0;
-                    wave.set_position(pos_f);
-                    wave.set_freq(freq_f);
+            .collect_vec();
+        if self.has_reference_legend() {
+            text.push(describe_indicator("No data", &self.indicators.no_data));
+        }
+        text
+    }
+
+    fn has_reference_legend(&self) -> bool {
+        self.inner
+            .iter()
+            .any(|graph| graph.settings.legend && !graph.settings.categorical)
+    }
+
+    fn legend(&self, graphs: &[GraphCells]) -> Legend {
+        let mut legend = Legend::default();
+        for (index, (inner, graph)) in self.inner.iter().zip(graphs).enumerate() {
+            let Some(sounds) = &graph.class_sounds else {
+                continue;
+            };
+            for (class, (value, sound)) in inner.classes().into_iter().zip(sounds).enumerate() {
+                let tones = self.step_tones(graphs, 0.0, &AudioIndicator::Silence, |i, _| {
+                    (i == index).then_some(class as f64)
+                });
+                legend.push(self.label(index, sound.describe(value)), tones);
+            }
+            for (value, label) in inner.reference_values() {
+                let tones = self.step_tones(graphs, 0.0, &AudioIndicator::Silence, |i, _| {
+                    (i == index).then_some(value)
+                });
+                legend.push(self.label(index, label), tones);
+            }
+        }
+        if self.has_reference_legend() {
+            let no_data = &self.indicators.no_data;
+            legend.push(
+                describe_indicator("No data", no_data),
+                self.step_tones(graphs, 0.0, no_data, |_, _| None),
+            );
+        }
+        legend
+    }
+
+    /// Builds the tones for one step of the graph
+    /// Graphs without a value for the step play the given indicator instead
+    fn step_tones<F>(
+        &self,
+        graphs: &[GraphCells],
+        pos: f64,
+        indicator: &AudioIndicator,
+        value_of: F,
+    ) -> Vec<Tone>
+    where
+        F: Fn(usize, &GraphCells) -> Option<f64>,
+    {
+        let mut different = false;
+        let mut tones = Vec::new();
+        for (index, graph) in graphs.iter().enumerate() {
+            match value_of(index, graph) {
+                Some(value) => tones.extend(graph.tones(value, pos, self.indicators.volume)),
+                None => {
+                    let (tone, is_different) =
+                        self.indicators
+                            .tone(indicator, graph.min_freq, graph.max_freq, pos);
+                    different |= is_different;
+                    // Indicators are played on the first voice of each graph
+                    tones.push(tone);
+                    tones.extend(vec![Tone::silent(pos); graph.voices.len() - 1]);
                 }
-                sleep(duration_per_sample_ms);
             }
         }
+        if self.indicators.needs_different_voice() {
+            tones.push(self.indicators.different_tone(
+                different,
+                graphs[0].min_freq,
+                graphs[0].max_freq,
+                pos,
+            ));
+        }
+        tones
+    }
+}
+
+impl Playable for RasterGraph {
+    fn score(&self) -> Score {
+        let graphs = self.inner.iter().map(RasterGraphInner::cells).collect_vec();
+        let waves = graphs
+            .iter()
+            .flat_map(|graph| graph.voices.iter().copied())
+            .collect_vec();
+        let mut voices = waves.clone();
+        if self.indicators.needs_different_voice() {
+            voices.push(different_wave(&waves));
+        }
+        let mut score = Score::new(voices);
+        self.legend(&graphs).write(&mut score);
+        let (row_count, col_count) = graphs[0].cells.dim();
+        let row_duration = graphs[0].row_duration;
+        let border = &self.indicators.border;
+        let no_data = &self.indicators.no_data;
+        let lines = self.inner[0]
+            .settings
+            .scan_order
+            .lines(row_count, col_count);
+        for line in lines {
+            let (Some(first), Some(last)) = (line.first(), line.last()) else {
+                continue;
+            };
+            score.start_row();
+            // Columns and rings don't have as many cells as a row
+            let cell_duration = row_duration.div_f64(line.len() as f64);
+            let start_pos = position(first.1, col_count);
+            score.push(
+                cell_duration,
+                self.step_tones(&graphs, start_pos, border, |_, _| None),
+            );
+            for (row, col) in line.iter().copied() {
+                let pos = position(col, col_count);
+                let tones =
+                    self.step_tones(&graphs, pos, no_data, |_, graph| graph.value(row, col));
+                score.push(cell_duration, tones);
+            }
+            let end_pos = position(last.1, col_count);
+            score.push(
+                cell_duration,
+                self.step_tones(&graphs, end_pos, border, |_, _| None),
+            );
+        }
+        score
     }
 }
 
+/// Replaces cells without a value with the average of their neighbours
+/// Cells with no neighbouring values are left empty
+fn fill_gaps_in(cells: &Array2<Option<f64>>) -> Array2<Option<f64>> {
+    let filled = interpolate_nans(&cells.map(|cell| cell.unwrap_or(f64::NAN)));
+    filled.map(|cell| Some(*cell).filter(|cell| cell.is_finite()))
+}
+
 fn interpolate_nans(arr: &Array2<f64>) -> Array2<f64> {
     let mut result = arr.clone();
     for ((x, y), el) in arr.indexed_iter() {
         if el.is_nan() {
             let mut sum = 0.0;
             let mut count = 0;
-            let neighbours = [
-                arr.get((x - 1, y + 1)),
-                arr.get((x - 1, y)),
-                arr.get((x - 1, y - 1)),
-                arr.get((x, y - 1)),
-                arr.get((x + 1, y - 1)),
-                arr.get((x + 1, y)),
-                arr.get((x + 1, y + 1)),
-                arr.get((x, y + 1)),
-            ];
-            for neighbour in neighbours {
-                if neighbour.copied().is_some_and(f64::is_finite) {
+            for (dx, dy) in [
+                (-1, 1),
+                (-1, 0),
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+                (0, 1),
+            ] {
+                // Subtracting from the first row or column would underflow
+                let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy))
+                else {
+                    continue;
+                };
+                if let Some(neighbour) = arr.get((nx, ny)).copied().filter(|x| x.is_finite()) {
                     count += 1;
-                    sum += *neighbour.unwrap();
+                    sum += neighbour;
                 }
             }
             *result.get_mut((x, y)).unwrap() = sum / count as f64;
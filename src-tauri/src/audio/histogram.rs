@@ -1,7 +1,15 @@
 use serde::{Deserialize, Serialize};
 
-use super::low_level::{AudioWave, Playable, Waveform};
-use std::{thread::sleep, time::Duration};
+use crate::state::settings::AudioIndicator;
+
+use super::{
+    indicators::{different_wave, Indicators},
+    legend::{format_value, Legend, REFERENCE_POINTS},
+    low_level::{Playable, Waveform},
+    mapping::PitchMapping,
+    score::{position, Score, Tone},
+};
+use std::time::Duration;
 
 /// Generate a sine wave audio signal for a given frequency.
 ///
@@ -13,62 +21,145 @@ use std::{thread::sleep, time::Duration};
 ///
 /// * A Vec<f64> containing the samples of the sine wave.
 
+#[derive(Clone, Debug)]
 pub struct AudioHistogram {
     y: Vec<f64>,
     waveform: Waveform,
     // ... other parameters
     settings: HistogramSettings,
+    indicators: Indicators,
 }
 
 impl AudioHistogram {
-    pub fn new(y: Vec<f64>, settings: HistogramSettings, waveform: Waveform) -> Self {
+    pub fn new(
+        y: Vec<f64>,
+        settings: HistogramSettings,
+        waveform: Waveform,
+        indicators: Indicators,
+    ) -> Self {
         AudioHistogram {
             y,
             waveform,
             settings,
+            indicators,
+        }
+    }
+
+    fn range(&self) -> (f64, f64) {
+        let min = self.y.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = self.y.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        (min, max)
+    }
+
+    /// The values played in the legend and a description of each
+    fn reference_values(&self) -> Vec<(f64, String)> {
+        if !self.settings.legend || self.y.is_empty() {
+            return Vec::new();
         }
+        let (min, max) = self.range();
+        REFERENCE_POINTS
+            .iter()
+            .map(|(fraction, name)| {
+                let value = min + (max - min) * fraction;
+                (value, format!("{name}, {}", format_value(value)))
+            })
+            .collect()
+    }
+
+    /// A description of each sound of the legend played before the histogram
+    pub fn legend_text(&self) -> Vec<String> {
+        self.reference_values()
+            .into_iter()
+            .map(|(_, label)| label)
+            .collect()
     }
 }
 
 impl Playable for AudioHistogram {
-    fn run<T>(&self, device: &cpal::Device, config: &cpal::StreamConfig)
-    where
-        T: cpal::Sample + cpal::SizedSample + cpal::FromSample<f64>,
-    {
-        // ... generate sound signal based on self.y and other parameters
-
+    fn score(&self) -> Score {
         let HistogramSettings {
             duration,
             min_freq,
             max_freq,
+            mapping,
+            legend: _,
         } = self.settings.clone();
-        let wave = AudioWave::new::<T>(self.waveform, device, config);
-        let _pos_f = -1.0;
-        let min = self
-            .y
-            .iter()
-            .cloned()
-            .min_by(|x, y| x.partial_cmp(y).unwrap())
-            .unwrap();
-        let max = self
-            .y
-            .iter()
-            .cloned()
-            .max_by(|x, y| x.partial_cmp(y).unwrap())
-            .unwrap();
-        let duration_per_sample_ms = Duration::from_millis((duration / self.y.len()) as u64);
+        let different_voice = self.indicators.border == AudioIndicator::Different;
+        let mut voices = vec![self.waveform];
+        if different_voice {
+            voices.push(different_wave(&voices));
+        }
+        let mut score = Score::new(voices);
+        let values = self.y.iter().copied().filter(|y| !y.is_nan());
+        let (Some(min), Some(max)) = (
+            values.clone().min_by(f64::total_cmp),
+            values.max_by(f64::total_cmp),
+        ) else {
+            // There is nothing to play
+            return score;
+        };
+        let duration_per_sample_ms = Duration::from_millis(duration as u64) / self.y.len() as u32;
         let y_range = max - min;
         let y_range = if y_range == 0.0 { 1.0 } else { y_range };
         let y_len = self.y.len();
-        let freq_range = max_freq - min_freq;
+        let mut legend = Legend::default();
+        for (value, label) in self.reference_values() {
+            let freq = mapping.freq((value - min) / y_range, min_freq, max_freq);
+            let mut tones = vec![Tone::new(freq, 0.0, self.indicators.volume)];
+            if different_voice {
+                tones.push(Tone::silent(0.0));
+            }
+            legend.push(label, tones);
+        }
+        legend.write(&mut score);
+        // The whole histogram is a single row so it can be skipped to after the legend
+        score.start_row();
+        let border_tones = |pos| {
+            let (tone, different) =
+                self.indicators
+                    .tone(&self.indicators.border, min_freq, max_freq, pos);
+            let mut tones = vec![tone];
+            if different_voice {
+                tones.push(
+                    self.indicators
+                        .different_tone(different, min_freq, max_freq, pos),
+                );
+            }
+            tones
+        };
+        score.push(duration_per_sample_ms, border_tones(-1.0));
         for (x, y) in self.y.iter().copied().enumerate() {
-            let freq_f = (y - min) / y_range * freq_range + min_freq;
-            let pos_f = x as f64 / (y_len - 1) as f64 * 2.0 - 1.0;
-            wave.set_position(pos_f);
-            wave.set_freq(freq_f);
-            sleep(duration_per_sample_ms);
+            let pos = position(x, y_len);
+            let tone = if y.is_nan() {
+                Tone::silent(pos)
+            } else {
+                let freq = mapping.freq((y - min) / y_range, min_freq, max_freq);
+                Tone::new(freq, pos, self.indicators.volume)
+            };
+            let mut tones = vec![tone];
+            if different_voice {
+                tones.push(Tone::silent(pos));
+            }
+            score.push(duration_per_sample_ms, tones);
         }
+        score.push(duration_per_sample_ms, border_tones(1.0));
+        score
+    }
+}
+
+/// Counts how many values fall into each of a number of equal width bins between the smallest and largest value
+pub fn bin_values(values: &[f64], bins: usize) -> Vec<f64> {
+    let bins = bins.max(1);
+    let mut counts = vec![0.0; bins];
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = if max > min { max - min } else { 1.0 };
+    for value in values {
+        let bin = (((value - min) / range) * bins as f64) as usize;
+        // The maximum value belongs in the last bin rather than one past it
+        counts[bin.min(bins - 1)] += 1.0;
     }
+    counts
 }
 
 pub fn generate_image_histogram(data: Vec<u8>) -> Vec<f64> {
@@ -79,17 +170,17 @@ pub fn generate_image_histogram(data: Vec<u8>) -> Vec<f64> {
     counts
 }
 
-pub fn play_histogram(counts: Vec<f64>, settings: HistogramSettings, wave: Waveform) {
-    let sonifier = AudioHistogram::new(counts, settings, wave);
-    sonifier.play();
-}
-
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
 pub struct HistogramSettings {
     /// The length the histogram should play for in milliseconds
     pub duration: usize,
     pub min_freq: f64,
     pub max_freq: f64,
+    #[serde(default)]
+    pub mapping: PitchMapping,
+    /// Plays reference tones for the lowest, highest and some values in between before the histogram
+    #[serde(default)]
+    pub legend: bool,
 }
 
 impl Default for HistogramSettings {
@@ -98,6 +189,42 @@ impl Default for HistogramSettings {
             duration: 5000,
             min_freq: 440.0,
             max_freq: 880.0,
+            mapping: PitchMapping::default(),
+            legend: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(y: Vec<f64>) -> AudioHistogram {
+        AudioHistogram::new(
+            y,
+            HistogramSettings::default(),
+            Waveform::Sine,
+            Indicators::default(),
+        )
+    }
+
+    #[test]
+    fn empty_histogram_has_no_steps() {
+        assert!(histogram(Vec::new()).score().steps.is_empty());
+        assert!(histogram(vec![f64::NAN]).score().steps.is_empty());
+    }
+
+    #[test]
+    fn nan_values_are_silent_and_ignored_for_the_range() {
+        let score = histogram(vec![0.0, f64::NAN, 1.0]).score();
+        let settings = HistogramSettings::default();
+        // The first and last steps are the borders
+        let freqs = score.steps[1..4]
+            .iter()
+            .map(|step| step.tones[0].freq)
+            .collect::<Vec<_>>();
+        assert_eq!(freqs[0], settings.min_freq);
+        assert_eq!(score.steps[2].tones[0].volume, 0.0);
+        assert_eq!(freqs[2], settings.max_freq);
+    }
+}
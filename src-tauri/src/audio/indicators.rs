@@ -0,0 +1,71 @@
+use crate::state::settings::{AudioIndicator, AudioSettings};
+
+use super::{score::Tone, Waveform};
+
+/// The parts of the users audio settings that apply to every sonification
+#[derive(Clone, Debug, PartialEq)]
+pub struct Indicators {
+    pub volume: f64,
+    pub no_data: AudioIndicator,
+    pub border: AudioIndicator,
+}
+
+impl Default for Indicators {
+    fn default() -> Self {
+        AudioSettings::default().indicators()
+    }
+}
+
+impl Indicators {
+    /// Whether an extra voice is needed to play [`AudioIndicator::Different`]
+    pub fn needs_different_voice(&self) -> bool {
+        self.no_data == AudioIndicator::Different || self.border == AudioIndicator::Different
+    }
+
+    /// The tone a graphs own voice should play in place of a value
+    /// The returned bool is true if the different voice should be played instead
+    pub fn tone(
+        &self,
+        indicator: &AudioIndicator,
+        min_freq: f64,
+        max_freq: f64,
+        pos: f64,
+    ) -> (Tone, bool) {
+        let tone = match indicator {
+            AudioIndicator::MinFreq => Tone::new(min_freq, pos, self.volume),
+            AudioIndicator::MaxFreq => Tone::new(max_freq, pos, self.volume),
+            // There is no way to speak in the middle of playback so verbal indicators are silent
+            AudioIndicator::Silence | AudioIndicator::Verbal | AudioIndicator::Different => {
+                Tone::silent(pos)
+            }
+        };
+        (tone, *indicator == AudioIndicator::Different)
+    }
+
+    /// The tone for the extra voice that plays [`AudioIndicator::Different`]
+    /// It sits in the middle of the frequency range so it can't be mistaken for an extreme value
+    pub fn different_tone(&self, playing: bool, min_freq: f64, max_freq: f64, pos: f64) -> Tone {
+        let freq = (min_freq * max_freq).sqrt();
+        if playing {
+            Tone::new(freq, pos, self.volume)
+        } else {
+            Tone::silent(pos)
+        }
+    }
+}
+
+/// Picks a waveform for the different voice that isn't used by any of the other voices
+pub fn different_wave(voices: &[Waveform]) -> Waveform {
+    [
+        Waveform::Square,
+        Waveform::Sawtooth,
+        Waveform::Triangle,
+        Waveform::Sine,
+        Waveform::Organ,
+        Waveform::Bell,
+        Waveform::Pluck,
+    ]
+    .into_iter()
+    .find(|wave| !voices.contains(wave))
+    .unwrap_or(Waveform::Square)
+}
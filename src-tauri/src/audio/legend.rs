@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use crate::state::settings::AudioIndicator;
+
+use super::score::{Score, Tone};
+
+/// How long each sound of a legend is played for
+const LEGEND_NOTE: Duration = Duration::from_millis(500);
+/// The silence between each sound of a legend
+const LEGEND_GAP: Duration = Duration::from_millis(250);
+/// The silence between the legend and the sonification it describes
+const LEGEND_END: Duration = Duration::from_millis(1000);
+
+/// The fractions of the way between the lowest and highest values that reference tones are played for
+pub const REFERENCE_POINTS: [(f64, &str); 5] = [
+    (0.0, "Lowest"),
+    (0.25, "Lower quarter"),
+    (0.5, "Middle"),
+    (0.75, "Upper quarter"),
+    (1.0, "Highest"),
+];
+
+/// Rounds a value to at most 2 decimal places for reading out
+pub fn format_value(value: f64) -> String {
+    ((value * 100.0).round() / 100.0).to_string()
+}
+
+/// Describes what an indicator sounds like, for example "No data is silent"
+pub fn describe_indicator(name: &str, indicator: &AudioIndicator) -> String {
+    let sound = match indicator {
+        AudioIndicator::Silence | AudioIndicator::Verbal => "is silent",
+        AudioIndicator::MinFreq => "plays the lowest pitch",
+        AudioIndicator::MaxFreq => "plays the highest pitch",
+        AudioIndicator::Different => "plays a different sound",
+    };
+    format!("{name} {sound}")
+}
+
+/// A sound and a description of what it means
+#[derive(Clone, Debug, PartialEq)]
+pub struct LegendEntry {
+    pub label: String,
+    /// One tone for each voice of the score the legend will be played before
+    pub tones: Vec<Tone>,
+}
+
+/// A sequence of labelled sounds played before a sonification so listeners know what to listen for
+/// The labels are sent back to the UI so they can be read out as each sound plays
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Legend {
+    entries: Vec<LegendEntry>,
+}
+
+impl Legend {
+    pub fn push(&mut self, label: impl Into<String>, tones: Vec<Tone>) {
+        self.entries.push(LegendEntry {
+            label: label.into(),
+            tones,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn text(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| entry.label.clone())
+            .collect()
+    }
+
+    /// Adds the legend to a score
+    /// This should be done before anything else is added so the legend plays first
+    /// The legend isn't counted as a row so skipping to a row still lands in the sonification itself
+    pub fn write(&self, score: &mut Score) {
+        if self.is_empty() {
+            return;
+        }
+        let silence = vec![Tone::silent(0.0); score.voices.len()];
+        for entry in &self.entries {
+            score.push(LEGEND_NOTE, entry.tones.clone());
+            score.push(LEGEND_GAP, silence.clone());
+        }
+        score.push(LEGEND_END, silence);
+    }
+}
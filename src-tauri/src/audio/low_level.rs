@@ -1,53 +1,108 @@
-use assert_no_alloc::*;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, FromSample, SizedSample};
-use cpal::{Stream, StreamConfig};
+use std::{
+    io,
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread::sleep,
+    time::Duration,
+};
+
 use fundsp::hacker::*;
 use serde::{Deserialize, Serialize};
 use strum::EnumIter;
 
-pub fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> (f64, f64))
-where
-    T: SizedSample + FromSample<f64>,
-{
-    for frame in output.chunks_mut(channels) {
-        let sample = next_sample();
-        let left = T::from_sample(sample.0);
-        let right: T = T::from_sample(sample.1);
+use super::{
+    midi::write_midi,
+    output::{CpalBackend, OutputBackend},
+    score::Score,
+    sequencer::{Sequencer, SequencerControl},
+    wav::{write_wav, WAV_SAMPLE_RATE},
+    AudioMessage,
+};
 
-        for (channel, sample) in frame.iter_mut().enumerate() {
-            if channel & 1 == 0 {
-                *sample = left;
-            } else {
-                *sample = right;
-            }
-        }
-    }
-}
+/// How often playback checks whether the sequencer has finished
+const CONTROL_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub trait Playable {
+    /// Builds the sequence of tones that make up this sonification
+    fn score(&self) -> Score;
+
     fn play(&self) {
-        let host = cpal::default_host();
-        let device = host.default_output_device().unwrap();
-        let config = device.default_output_config().unwrap();
+        // Nothing will ever be sent on this channel so playback runs to the end
+        let (_sender, control) = mpsc::channel();
+        self.play_with_control(&control);
+    }
 
-        match config.sample_format() {
-            cpal::SampleFormat::F32 => self.run::<f32>(&device, &config.into()),
-            cpal::SampleFormat::F64 => self.run::<f64>(&device, &config.into()),
-            cpal::SampleFormat::I16 => self.run::<i16>(&device, &config.into()),
-            cpal::SampleFormat::U8 => self.run::<u8>(&device, &config.into()),
-            cpal::SampleFormat::U16 => self.run::<u16>(&device, &config.into()),
-            _ => panic!("Unsupported format"),
+    /// Plays the sonification while listening for stop, pause, resume and skip messages
+    /// If a message to play something else arrives playback ends and it is returned
+    fn play_with_control(&self, control: &Receiver<AudioMessage>) -> Option<AudioMessage> {
+        match CpalBackend::default_output() {
+            Ok(mut backend) => self.play_on(&mut backend, control),
+            Err(err) => {
+                eprint!("{err}");
+                None
+            }
         }
     }
 
-    fn run<T>(&self, device: &cpal::Device, config: &cpal::StreamConfig)
-    where
-        T: cpal::Sample + cpal::SizedSample + cpal::FromSample<f64>;
+    /// Plays the sonification through the given output, listening for control messages the same as play_with_control
+    fn play_on<B: OutputBackend>(
+        &self,
+        backend: &mut B,
+        control: &Receiver<AudioMessage>,
+    ) -> Option<AudioMessage> {
+        play_score(&self.score(), backend, control)
+    }
+
+    /// Renders the sonification to a WAV file rather than playing it through an output device
+    fn save_wav<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let samples = self.score().render(WAV_SAMPLE_RATE as f64);
+        write_wav(path, WAV_SAMPLE_RATE, &samples)
+    }
+
+    /// Writes the sonification to a Standard MIDI File with a track for each voice
+    fn save_midi<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        write_midi(path, &self.score())
+    }
+}
+
+pub fn play_score<B: OutputBackend>(
+    score: &Score,
+    backend: &mut B,
+    control: &Receiver<AudioMessage>,
+) -> Option<AudioMessage> {
+    let state = Arc::new(SequencerControl::default());
+    let sequencer = Sequencer::new(score.clone(), backend.sample_rate(), state.clone());
+    // Dropping the stream ends playback so it needs to be kept until we return
+    let _stream = match backend.start(sequencer) {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprint!("{err}");
+            return None;
+        }
+    };
+    loop {
+        if state.finished() {
+            return None;
+        }
+        // Timing is handled by the sequencer so this only needs to be responsive to messages
+        match control.recv_timeout(CONTROL_POLL_INTERVAL) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => sleep(CONTROL_POLL_INTERVAL),
+            Ok(AudioMessage::Stop) => return None,
+            Ok(AudioMessage::Pause) => state.pause(),
+            Ok(AudioMessage::Resume) => state.resume(),
+            Ok(AudioMessage::SkipLegend) => state.seek(0),
+            Ok(AudioMessage::SkipToRow(row)) => state.seek(row),
+            Ok(message) => return Some(message),
+        }
+    }
 }
 
 #[derive(
-    Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize, specta::Type, EnumIter,
+    Clone, Copy, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize, specta::Type, EnumIter,
 )]
 pub enum Waveform {
     #[default]
@@ -55,69 +110,85 @@ pub enum Waveform {
     Square,
     Triangle,
     Sawtooth,
+    /// White noise through a band pass filter centred on the frequency
+    WhiteNoise,
+    /// Pink noise through a band pass filter centred on the frequency
+    PinkNoise,
+    /// A frequency modulated tone with inharmonic overtones that dies away like a struck bell
+    Bell,
+    /// A bright tone that darkens and dies away like a plucked string
+    Pluck,
+    /// The first few harmonics added together like an organ
+    Organ,
 }
 
-pub struct AudioWave {
-    freq: Shared<f64>,
-    pos: Shared<f64>,
-    // This is here as the stream cannot be dropped without ending the audio playback
-    #[allow(dead_code)]
-    stream: Stream,
-}
+/// How sharply the noise waveforms are filtered, higher values sound more like a pitch
+const NOISE_Q: f64 = 4.0;
+/// Filtering removes most of the energy of noise so it is boosted to be about as loud as the other waveforms
+const NOISE_GAIN: f64 = 6.0;
 
-impl AudioWave {
-    pub fn new<T: cpal::Sample + cpal::SizedSample + cpal::FromSample<f64>>(
-        wave: Waveform,
-        device: &Device,
-        config: &StreamConfig,
-    ) -> Self {
-        match wave {
-            Waveform::Sine => Self::inner_new::<T, _>(sine(), device, config),
-            Waveform::Triangle => Self::inner_new::<T, _>(triangle(), device, config),
-            Waveform::Square => Self::inner_new::<T, _>(square(), device, config),
-            Waveform::Sawtooth => Self::inner_new::<T, _>(saw(), device, config),
+impl Waveform {
+    /// Builds the audio graph for this waveform, tuned, panned and scaled by the given shared values
+    pub fn voice(
+        self,
+        freq: &Shared<f64>,
+        pos: &Shared<f64>,
+        volume: &Shared<f64>,
+    ) -> Box<dyn AudioUnit64> {
+        macro_rules! voice {
+            ($source:expr) => {
+                Box::new((($source) * var(volume) | var(pos)) >> panner())
+            };
+        }
+        match self {
+            Waveform::Sine => voice!(var(freq) >> sine()),
+            Waveform::Triangle => voice!(var(freq) >> triangle()),
+            Waveform::Square => voice!(var(freq) >> square()),
+            Waveform::Sawtooth => voice!(var(freq) >> saw()),
+            Waveform::WhiteNoise => {
+                voice!((noise() | var(freq) | dc(NOISE_Q)) >> bandpass() * NOISE_GAIN)
+            }
+            Waveform::PinkNoise => {
+                voice!((pink() | var(freq) | dc(NOISE_Q)) >> bandpass() * NOISE_GAIN)
+            }
+            Waveform::Bell => {
+                // A modulator at 3.5 times the frequency gives the inharmonic partials of a bell
+                // Its depth decays faster than the volume so the tone gets purer as it fades
+                let modulator =
+                    (var(freq) * 3.5 >> sine()) * (var(freq) * envelope(|t: f64| (-t * 3.0).exp()));
+                voice!((var(freq) + modulator >> sine()) * envelope(|t: f64| (-t * 1.5).exp()))
+            }
+            Waveform::Pluck => {
+                let cutoff = envelope(|t: f64| 300.0 + 6000.0 * (-t * 8.0).exp());
+                let decay = envelope(|t: f64| (-t * 3.0).exp());
+                voice!(((var(freq) >> saw()) | cutoff) >> lowpole() * decay)
+            }
+            Waveform::Organ => voice!(
+                (var(freq) >> sine()) * 0.5
+                    + (var(freq) * 2.0 >> sine()) * 0.25
+                    + (var(freq) * 3.0 >> sine()) * 0.15
+                    + (var(freq) * 4.0 >> sine()) * 0.1
+            ),
         }
     }
 
-    fn inner_new<
-        S: cpal::Sample + cpal::SizedSample + cpal::FromSample<f64>,
-        T: AudioNode<Sample = f64, Inputs = U1, Outputs = U1> + Send + 'static,
-    >(
-        source: An<T>,
-        device: &Device,
-        config: &StreamConfig,
-    ) -> Self {
-        let freq = shared(440.0);
-        let c = var(&freq) >> source;
-        let pos = shared(-1.0);
-        let mut c = (c | var(&pos)) >> panner();
-        c.set_sample_rate(config.sample_rate.0 as f64);
-        c.allocate();
-        let mut next_value = move || assert_no_alloc(|| c.get_stereo());
-
-        let err_fn = |err| panic!("an error occurred on stream: {}", err);
-
-        let channels = config.channels as usize;
-        let stream = device
-            .build_output_stream(
-                config,
-                move |data: &mut [S], _: &cpal::OutputCallbackInfo| {
-                    write_data(data, channels, &mut &mut next_value);
-                },
-                err_fn,
-                None,
-            )
-            .unwrap();
-        stream.play().unwrap();
-
-        Self { freq, pos, stream }
-    }
-
-    pub fn set_position(&self, pos: f64) {
-        self.pos.set(pos)
+    /// Whether the waveform fades away on its own so needs restarting for every note
+    pub fn is_struck(self) -> bool {
+        matches!(self, Waveform::Bell | Waveform::Pluck)
     }
 
-    pub fn set_freq(&self, freq: f64) {
-        self.freq.set(freq)
+    /// A short description for reading out, for example "a sine wave"
+    pub fn description(self) -> &'static str {
+        match self {
+            Waveform::Sine => "a sine wave",
+            Waveform::Square => "a square wave",
+            Waveform::Triangle => "a triangle wave",
+            Waveform::Sawtooth => "a sawtooth wave",
+            Waveform::WhiteNoise => "white noise",
+            Waveform::PinkNoise => "pink noise",
+            Waveform::Bell => "a bell",
+            Waveform::Pluck => "a plucked string",
+            Waveform::Organ => "an organ",
+        }
     }
 }
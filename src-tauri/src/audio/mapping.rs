@@ -0,0 +1,92 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use strum::EnumIter;
+
+const SEMITONES: [u32; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const PENTATONIC: [u32; 5] = [0, 2, 4, 7, 9];
+const MAJOR: [u32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// How values are turned into pitches
+#[derive(
+    Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize, specta::Type, EnumIter,
+)]
+pub enum PitchMapping {
+    /// Equal steps in value give equal steps in frequency
+    #[default]
+    Linear,
+    /// Equal steps in value give equal musical intervals
+    Logarithmic,
+    /// Snaps to the nearest note of the chromatic scale starting at the minimum frequency
+    Semitones,
+    /// Snaps to the nearest note of the major pentatonic scale starting at the minimum frequency
+    Pentatonic,
+    /// Snaps to the nearest note of the major scale starting at the minimum frequency
+    Major,
+}
+
+impl PitchMapping {
+    /// Maps a value that has been normalised so the minimum is 0.0 and the maximum is 1.0 to a frequency
+    pub fn freq(self, value: f64, min_freq: f64, max_freq: f64) -> f64 {
+        // Logarithmic scales make no sense with a minimum frequency of 0
+        if min_freq <= 0.0 && self != Self::Linear {
+            return Self::Linear.freq(value, min_freq, max_freq);
+        }
+        match self {
+            Self::Linear => value * (max_freq - min_freq) + min_freq,
+            Self::Logarithmic => min_freq * (max_freq / min_freq).powf(value),
+            Self::Semitones | Self::Pentatonic | Self::Major => {
+                let notes = self.notes(min_freq, max_freq);
+                let index = (value.clamp(0.0, 1.0) * (notes.len() - 1) as f64).round();
+                notes[index as usize]
+            }
+        }
+    }
+
+    /// Every frequency a scale based mapping can produce in ascending order
+    /// Continuous mappings just return the minimum and maximum frequencies
+    pub fn notes(self, min_freq: f64, max_freq: f64) -> Vec<f64> {
+        let scale: &[u32] = match self {
+            Self::Linear | Self::Logarithmic => return vec![min_freq, max_freq],
+            Self::Semitones => &SEMITONES,
+            Self::Pentatonic => &PENTATONIC,
+            Self::Major => &MAJOR,
+        };
+        let notes = (0..)
+            .filter(|semitone| scale.contains(&(semitone % 12)))
+            .map(|semitone| min_freq * 2_f64.powf(semitone as f64 / 12.0))
+            .take_while(|freq| *freq <= max_freq * 1.0001)
+            .collect::<Vec<_>>();
+        if notes.is_empty() {
+            vec![min_freq]
+        } else {
+            notes
+        }
+    }
+}
+
+impl FromStr for PitchMapping {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(Self::Linear),
+            "logarithmic" | "log" => Ok(Self::Logarithmic),
+            "semitones" => Ok(Self::Semitones),
+            "pentatonic" => Ok(Self::Pentatonic),
+            "major" => Ok(Self::Major),
+            _ => Err("Not a valid pitch mapping"),
+        }
+    }
+}
+
+impl Display for PitchMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Linear => f.write_str("linear"),
+            Self::Logarithmic => f.write_str("logarithmic"),
+            Self::Semitones => f.write_str("semitones"),
+            Self::Pentatonic => f.write_str("pentatonic"),
+            Self::Major => f.write_str("major"),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use super::{score::Score, Waveform};
+
+/// Ticks per quarter note
+const DIVISION: u16 = 480;
+/// Microseconds per quarter note, 120 beats per minute
+const TEMPO: u32 = 500_000;
+/// The channel General MIDI reserves for percussion
+const DRUM_CHANNEL: u8 = 9;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CONTROL_CHANGE: u8 = 0xB0;
+const PROGRAM_CHANGE: u8 = 0xC0;
+const PAN: u8 = 10;
+
+/// Converts a frequency to the nearest MIDI note number
+pub fn note_number(freq: f64) -> u8 {
+    (69.0 + 12.0 * (freq / 440.0).log2())
+        .round()
+        .clamp(0.0, 127.0) as u8
+}
+
+fn ticks(elapsed: Duration) -> u32 {
+    (elapsed.as_micros() as f64 / TEMPO as f64 * DIVISION as f64).round() as u32
+}
+
+/// The General MIDI instrument closest to each waveform
+fn program(wave: Waveform) -> u8 {
+    match wave {
+        // Ocarina
+        Waveform::Sine => 79,
+        // Flute
+        Waveform::Triangle => 73,
+        // Square lead
+        Waveform::Square => 80,
+        // Sawtooth lead
+        Waveform::Sawtooth => 81,
+        // Seashore
+        Waveform::WhiteNoise => 122,
+        // Breath noise
+        Waveform::PinkNoise => 121,
+        // Tubular bells
+        Waveform::Bell => 14,
+        // Acoustic guitar (nylon)
+        Waveform::Pluck => 24,
+        // Drawbar organ
+        Waveform::Organ => 16,
+    }
+}
+
+/// Skips the percussion channel so every voice gets a pitched instrument
+fn voice_channel(voice: usize) -> u8 {
+    let channel = (voice % 15) as u8;
+    if channel >= DRUM_CHANNEL {
+        channel + 1
+    } else {
+        channel
+    }
+}
+
+fn write_var_len(bytes: &mut Vec<u8>, mut value: u32) {
+    let mut buffer = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        buffer.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.extend(buffer.into_iter().rev());
+}
+
+/// Collects the events of a single track, keeping track of the time of the last one
+#[derive(Default)]
+struct Track {
+    bytes: Vec<u8>,
+    last_tick: u32,
+}
+
+impl Track {
+    fn event(&mut self, tick: u32, data: &[u8]) {
+        write_var_len(&mut self.bytes, tick.saturating_sub(self.last_tick));
+        self.last_tick = self.last_tick.max(tick);
+        self.bytes.extend_from_slice(data);
+    }
+
+    fn meta(&mut self, tick: u32, kind: u8, data: &[u8]) {
+        let mut event = vec![0xFF, kind];
+        write_var_len(&mut event, data.len() as u32);
+        event.extend_from_slice(data);
+        self.event(tick, &event);
+    }
+
+    fn write_to(mut self, file: &mut impl Write) -> io::Result<()> {
+        // End of track
+        self.meta(self.last_tick, 0x2F, &[]);
+        file.write_all(b"MTrk")?;
+        file.write_all(&(self.bytes.len() as u32).to_be_bytes())?;
+        file.write_all(&self.bytes)
+    }
+}
+
+/// Writes a score to a type 1 Standard MIDI File with one track for each voice
+/// Each step becomes a note with the stereo position sent as a pan controller
+pub fn write_midi<P: AsRef<Path>>(path: P, score: &Score) -> io::Result<()> {
+    let mut tempo_track = Track::default();
+    tempo_track.meta(0, 0x51, &TEMPO.to_be_bytes()[1..]);
+
+    let mut tracks = score
+        .voices
+        .iter()
+        .enumerate()
+        .map(|(voice, wave)| {
+            let mut track = Track::default();
+            let name = format!("Voice {} ({:?})", voice + 1, wave);
+            track.meta(0, 0x03, name.as_bytes());
+            track.event(0, &[PROGRAM_CHANGE | voice_channel(voice), program(*wave)]);
+            track
+        })
+        .collect::<Vec<_>>();
+    let mut pans = vec![None; tracks.len()];
+
+    let mut elapsed = Duration::ZERO;
+    for step in &score.steps {
+        let start = ticks(elapsed);
+        elapsed += step.duration;
+        let end = ticks(elapsed);
+        // Steps too short to last a single tick can't be represented
+        if end == start {
+            continue;
+        }
+        for (voice, (track, tone)) in tracks.iter_mut().zip(&step.tones).enumerate() {
+            if tone.volume <= 0.0 || tone.freq <= 0.0 {
+                continue;
+            }
+            let channel = voice_channel(voice);
+            let pan = (((tone.pos + 1.0) / 2.0) * 127.0).round().clamp(0.0, 127.0) as u8;
+            if pans[voice] != Some(pan) {
+                track.event(start, &[CONTROL_CHANGE | channel, PAN, pan]);
+                pans[voice] = Some(pan);
+            }
+            let note = note_number(tone.freq);
+            let velocity = (tone.volume * 100.0).round().clamp(1.0, 127.0) as u8;
+            track.event(start, &[NOTE_ON | channel, note, velocity]);
+            track.event(end, &[NOTE_OFF | channel, note, 0]);
+        }
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"MThd")?;
+    file.write_all(&6_u32.to_be_bytes())?;
+    // Multiple tracks played at the same time
+    file.write_all(&1_u16.to_be_bytes())?;
+    file.write_all(&(tracks.len() as u16 + 1).to_be_bytes())?;
+    file.write_all(&DIVISION.to_be_bytes())?;
+    tempo_track.write_to(&mut file)?;
+    for track in tracks {
+        track.write_to(&mut file)?;
+    }
+    file.flush()
+}
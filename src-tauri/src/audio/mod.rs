@@ -3,17 +3,31 @@
 
 pub mod graph;
 pub mod histogram;
+pub mod indicators;
+pub mod legend;
 pub mod low_level;
+pub mod mapping;
+pub mod midi;
+pub mod output;
+pub mod score;
+pub mod sequencer;
+pub mod soundscape;
+#[cfg(test)]
+mod test_utils;
+pub mod trace;
+pub mod wav;
 
 pub use low_level::Waveform;
 use std::sync::mpsc::{self, Receiver};
 use std::thread;
 
 use assert_no_alloc::*;
-use ndarray::Array2;
 
-use graph::{play_rasta, RasterGraphSettings};
-use histogram::{play_histogram, HistogramSettings};
+use graph::RasterGraph;
+use histogram::AudioHistogram;
+use low_level::Playable;
+use soundscape::Soundscape;
+use trace::GeometryTrace;
 
 #[cfg(debug_assertions)] // required when disable_release is set (default)
 #[global_allocator]
@@ -21,29 +35,51 @@ static A: AllocDisabler = AllocDisabler;
 
 #[derive(Clone, Debug)]
 pub enum AudioMessage {
-    PlayHistogram(Vec<f64>, HistogramSettings, Waveform),
-    PlayRaster(Array2<f64>, f64, f64, Option<f64>, RasterGraphSettings),
+    PlayHistogram(AudioHistogram),
+    PlayRaster(RasterGraph),
+    /// Traces the outline of a vector geometry
+    PlayTrace(GeometryTrace),
+    /// Plays the features around a point
+    PlaySoundscape(Soundscape),
+    /// Ends whatever is currently playing
+    Stop,
+    Pause,
+    Resume,
+    /// Jumps past the legend to the start of the sonification itself
+    SkipLegend,
+    /// Jumps to the start of the given row of a raster graph
+    /// When the graph isn't played row by row this is the column or ring instead
+    SkipToRow(usize),
 }
 
-pub fn get_audio() -> mpsc::SyncSender<AudioMessage> {
-    let (tx, rx) = mpsc::sync_channel(0);
+pub fn get_audio() -> mpsc::Sender<AudioMessage> {
+    let (tx, rx) = mpsc::channel();
     // Note we do not save the returned JoinHandle and so create a detached thread.
     thread::spawn(move || audio_thread(rx));
     tx
 }
 
 fn audio_thread(rx: Receiver<AudioMessage>) {
+    // Set when a new sonification is requested while another is still playing
+    let mut next = None;
     loop {
-        let msg = rx
-            .recv()
-            .expect("The Sender related to the audio thread has been dropped");
-        match msg {
-            AudioMessage::PlayHistogram(graph, settings, wave) => {
-                play_histogram(graph, settings, wave)
-            }
-            AudioMessage::PlayRaster(data, min, max, no_data_value, settings) => {
-                play_rasta(vec![(data, min, max, no_data_value, settings)]);
-            }
+        let msg = match next.take() {
+            Some(msg) => msg,
+            None => rx
+                .recv()
+                .expect("The Sender related to the audio thread has been dropped"),
+        };
+        next = match msg {
+            AudioMessage::PlayHistogram(histogram) => histogram.play_with_control(&rx),
+            AudioMessage::PlayRaster(graph) => graph.play_with_control(&rx),
+            AudioMessage::PlayTrace(trace) => trace.play_with_control(&rx),
+            AudioMessage::PlaySoundscape(soundscape) => soundscape.play_with_control(&rx),
+            // Nothing is playing so there is nothing to control
+            AudioMessage::Stop
+            | AudioMessage::Pause
+            | AudioMessage::Resume
+            | AudioMessage::SkipLegend
+            | AudioMessage::SkipToRow(_) => None,
         };
     }
 }
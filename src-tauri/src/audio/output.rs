@@ -0,0 +1,296 @@
+use assert_no_alloc::*;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, FromSample, SampleFormat, SizedSample, Stream, SupportedStreamConfig};
+
+use super::sequencer::Sequencer;
+
+/// Somewhere the samples of a sequencer can be sent
+pub trait OutputBackend {
+    /// Kept alive for as long as the sequencer should keep playing
+    type Stream;
+
+    /// The sample rate the sequencer needs to be built for
+    fn sample_rate(&self) -> f64;
+
+    /// Starts pulling samples from the sequencer
+    /// Playback ends once the sequencer finishes or the returned stream is dropped
+    fn start(&mut self, sequencer: Sequencer) -> Result<Self::Stream, String>;
+}
+
+/// Plays through an output device of the system
+pub struct CpalBackend {
+    device: Device,
+    config: SupportedStreamConfig,
+}
+
+impl CpalBackend {
+    /// Uses the default output device of the default host
+    pub fn default_output() -> Result<Self, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| "No audio output device available".to_string())?;
+        let config = device
+            .default_output_config()
+            .map_err(|err| format!("Failed to get the audio output config: {err}"))?;
+        Ok(Self { device, config })
+    }
+}
+
+impl OutputBackend for CpalBackend {
+    type Stream = Stream;
+
+    fn sample_rate(&self) -> f64 {
+        self.config.sample_rate().0 as f64
+    }
+
+    fn start(&mut self, sequencer: Sequencer) -> Result<Stream, String> {
+        match self.config.sample_format() {
+            SampleFormat::F32 => self.start_stream::<f32>(sequencer),
+            SampleFormat::F64 => self.start_stream::<f64>(sequencer),
+            SampleFormat::I8 => self.start_stream::<i8>(sequencer),
+            SampleFormat::I16 => self.start_stream::<i16>(sequencer),
+            SampleFormat::I32 => self.start_stream::<i32>(sequencer),
+            SampleFormat::I64 => self.start_stream::<i64>(sequencer),
+            SampleFormat::U8 => self.start_stream::<u8>(sequencer),
+            SampleFormat::U16 => self.start_stream::<u16>(sequencer),
+            SampleFormat::U32 => self.start_stream::<u32>(sequencer),
+            SampleFormat::U64 => self.start_stream::<u64>(sequencer),
+            format => Err(format!("Unsupported sample format {format:?}")),
+        }
+    }
+}
+
+impl CpalBackend {
+    fn start_stream<S>(&self, mut sequencer: Sequencer) -> Result<Stream, String>
+    where
+        S: SizedSample + FromSample<f64>,
+    {
+        let mut next_value = move || assert_no_alloc(|| sequencer.next_sample());
+        let err_fn = |err| eprint!("an error occurred on stream: {}", err);
+        let channels = self.config.channels() as usize;
+        let stream = self
+            .device
+            .build_output_stream(
+                &self.config.config(),
+                move |data: &mut [S], _: &cpal::OutputCallbackInfo| {
+                    write_data(data, channels, &mut next_value);
+                },
+                err_fn,
+                None,
+            )
+            .map_err(|err| format!("Failed to open the audio output stream: {err}"))?;
+        stream
+            .play()
+            .map_err(|err| format!("Failed to start the audio output stream: {err}"))?;
+        Ok(stream)
+    }
+}
+
+pub fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> (f64, f64))
+where
+    T: SizedSample + FromSample<f64>,
+{
+    for frame in output.chunks_mut(channels) {
+        let sample = next_sample();
+        let left = T::from_sample(sample.0);
+        let right: T = T::from_sample(sample.1);
+
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            if channel & 1 == 0 {
+                *sample = left;
+            } else {
+                *sample = right;
+            }
+        }
+    }
+}
+
+/// Collects every sample in memory instead of playing them
+/// This runs as fast as possible so sonifications can be rendered to files or checked without a sound card
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CaptureBackend {
+    sample_rate: f64,
+    samples: Vec<(f64, f64)>,
+}
+
+impl CaptureBackend {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            sample_rate,
+            samples: Vec::new(),
+        }
+    }
+
+    /// The left and right samples of everything captured so far
+    pub fn samples(&self) -> &[(f64, f64)] {
+        &self.samples
+    }
+
+    pub fn into_samples(self) -> Vec<(f64, f64)> {
+        self.samples
+    }
+}
+
+impl OutputBackend for CaptureBackend {
+    type Stream = ();
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn start(&mut self, mut sequencer: Sequencer) -> Result<(), String> {
+        // The sequencer only notices it has finished on the call after its last sample so stop at its length instead
+        let len = sequencer.len();
+        self.samples.reserve(len);
+        self.samples
+            .extend(std::iter::repeat_with(|| sequencer.next_sample()).take(len));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use ndarray::array;
+
+    use super::*;
+    use crate::{
+        audio::{
+            graph::{RasterGraph, RasterGraphSettings, ScanOrder},
+            histogram::{AudioHistogram, HistogramSettings},
+            indicators::Indicators,
+            low_level::Playable,
+            test_utils::{rms, zero_crossing_frequency},
+            Waveform,
+        },
+        state::settings::AudioIndicator,
+    };
+
+    const SAMPLE_RATE: f64 = 16000.0;
+    /// The 5ms crossfade between steps
+    const FADE_LEN: usize = 80;
+
+    fn capture(playable: &impl Playable) -> Vec<(f64, f64)> {
+        let mut backend = CaptureBackend::new(SAMPLE_RATE);
+        let sequencer = Sequencer::new(playable.score(), SAMPLE_RATE, Arc::default());
+        backend.start(sequencer).unwrap();
+        backend.into_samples()
+    }
+
+    /// Checks each step is held for its duration at the expected frequency and position
+    /// Only hard left and hard right positions are expected
+    fn assert_steps(samples: &[(f64, f64)], step: Duration, expected: &[(f64, f64)]) {
+        let step_len = (step.as_secs_f64() * SAMPLE_RATE).round() as usize;
+        // The last step fades out after the score ends
+        assert_eq!(samples.len(), step_len * expected.len() + FADE_LEN);
+        for (index, (freq, pos)) in expected.iter().copied().enumerate() {
+            // Skip the crossfades at either end of the step
+            let step = &samples[index * step_len + FADE_LEN..(index + 1) * step_len - FADE_LEN];
+            let (left, right): (Vec<_>, Vec<_>) = step.iter().copied().unzip();
+            let (heard, silent) = if pos < 0.0 {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            assert!(
+                rms(silent.iter().copied()) < 0.01 * rms(heard.iter().copied()),
+                "step {index} wasn't panned to {pos}"
+            );
+            let measured = zero_crossing_frequency(&heard, SAMPLE_RATE);
+            assert!(
+                (measured - freq).abs() / freq < 0.02,
+                "step {index} played at {measured}Hz instead of {freq}Hz"
+            );
+        }
+    }
+
+    fn indicators() -> Indicators {
+        Indicators {
+            volume: 1.0,
+            no_data: AudioIndicator::MinFreq,
+            border: AudioIndicator::MinFreq,
+        }
+    }
+
+    #[test]
+    fn captures_a_histogram() {
+        let settings = HistogramSettings {
+            duration: 800,
+            ..Default::default()
+        };
+        let histogram = AudioHistogram::new(vec![0.0, 1.0], settings, Waveform::Sine, indicators());
+        let samples = capture(&histogram);
+        // The borders are played at the lowest frequency either side of the values
+        assert_steps(
+            &samples,
+            Duration::from_millis(400),
+            &[(440.0, -1.0), (440.0, -1.0), (880.0, 1.0), (440.0, 1.0)],
+        );
+    }
+
+    #[test]
+    fn captures_a_raster_graph() {
+        let settings = RasterGraphSettings {
+            row_duration: Duration::from_millis(400),
+            min_freq: 220.0,
+            max_freq: 880.0,
+            rows: 2,
+            cols: 2,
+            wave: Waveform::Sine,
+            ..Default::default()
+        };
+        let data = array![[0.0, 1.0], [1.0, 0.0]];
+        let graph = RasterGraph::new(vec![(data, 0.0, 1.0, None, settings)], indicators());
+        let samples = capture(&graph);
+        assert_steps(
+            &samples,
+            Duration::from_millis(200),
+            &[
+                (220.0, -1.0),
+                (220.0, -1.0),
+                (880.0, 1.0),
+                (220.0, 1.0),
+                (220.0, -1.0),
+                (880.0, -1.0),
+                (220.0, 1.0),
+                (220.0, 1.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn columns_share_the_row_duration_between_their_cells() {
+        let settings = RasterGraphSettings {
+            row_duration: Duration::from_millis(800),
+            min_freq: 220.0,
+            max_freq: 880.0,
+            rows: 4,
+            cols: 2,
+            wave: Waveform::Sine,
+            scan_order: ScanOrder::Columns,
+            ..Default::default()
+        };
+        let data = array![[0.0, 1.0], [1.0, 0.0], [0.0, 1.0], [1.0, 0.0]];
+        let graph = RasterGraph::new(vec![(data, 0.0, 1.0, None, settings)], indicators());
+        let samples = capture(&graph);
+        assert_steps(
+            &samples,
+            Duration::from_millis(200),
+            &[
+                (220.0, -1.0),
+                (220.0, -1.0),
+                (880.0, -1.0),
+                (220.0, -1.0),
+                (880.0, -1.0),
+                (220.0, -1.0),
+                (220.0, 1.0),
+                (880.0, 1.0),
+                (220.0, 1.0),
+                (880.0, 1.0),
+                (220.0, 1.0),
+                (220.0, 1.0),
+            ],
+        );
+    }
+}
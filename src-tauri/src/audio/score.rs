@@ -0,0 +1,92 @@
+use std::{sync::Arc, time::Duration};
+
+use super::{
+    low_level::Waveform,
+    output::{CaptureBackend, OutputBackend},
+    sequencer::Sequencer,
+};
+
+/// The frequency, stereo position and volume of a single voice
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    pub freq: f64,
+    /// -1.0 is hard left and 1.0 is hard right
+    pub pos: f64,
+    pub volume: f64,
+}
+
+impl Tone {
+    pub fn new(freq: f64, pos: f64, volume: f64) -> Self {
+        Self { freq, pos, volume }
+    }
+
+    pub fn silent(pos: f64) -> Self {
+        Self {
+            freq: 0.0,
+            pos,
+            volume: 0.0,
+        }
+    }
+}
+
+/// Spreads indexes evenly across the stereo field from hard left to hard right
+pub fn position(index: usize, len: usize) -> f64 {
+    if len <= 1 {
+        0.0
+    } else {
+        index as f64 / (len - 1) as f64 * 2.0 - 1.0
+    }
+}
+
+/// A period of time for which every voice holds a single tone
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub duration: Duration,
+    /// One tone for each voice in the score
+    pub tones: Vec<Tone>,
+}
+
+/// Everything needed to play a sonification independent of where it is being played to
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Score {
+    pub voices: Vec<Waveform>,
+    pub steps: Vec<Step>,
+    /// The index of the first step of each row, used to skip around during playback
+    /// For graphs not played row by row these are the lines of the scan order instead
+    pub rows: Vec<usize>,
+}
+
+impl Score {
+    pub fn new(voices: Vec<Waveform>) -> Self {
+        Self {
+            voices,
+            steps: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Marks the next step pushed as the start of a new row
+    pub fn start_row(&mut self) {
+        self.rows.push(self.steps.len())
+    }
+
+    pub fn push(&mut self, duration: Duration, tones: Vec<Tone>) {
+        debug_assert_eq!(tones.len(), self.voices.len());
+        self.steps.push(Step { duration, tones })
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().map(|step| step.duration).sum()
+    }
+
+    /// Renders the score to stereo samples at the given sample rate
+    /// The voices sounding in each step are mixed at equal levels so the output stays within -1.0 to 1.0
+    pub fn render(&self, sample_rate: f64) -> Vec<(f64, f64)> {
+        let mut capture = CaptureBackend::new(sample_rate);
+        let sequencer = Sequencer::new(self.clone(), sample_rate, Arc::default());
+        capture
+            .start(sequencer)
+            .expect("Capturing samples can't fail");
+        capture.into_samples()
+    }
+}
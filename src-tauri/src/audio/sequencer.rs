@@ -0,0 +1,330 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use fundsp::hacker::*;
+use itertools::Itertools;
+
+use super::score::{Score, Tone};
+
+/// How long it takes to fade from one tone to the next
+/// Long enough to remove clicks but short enough that cells still sound distinct
+const CROSSFADE: Duration = Duration::from_millis(5);
+
+/// Used in place of a row index when no seek has been requested
+const NO_SEEK: usize = usize::MAX;
+
+/// Lets another thread control a sequencer running inside an audio callback without locking
+#[derive(Debug)]
+pub struct SequencerControl {
+    paused: AtomicBool,
+    seek: AtomicUsize,
+    finished: AtomicBool,
+}
+
+impl Default for SequencerControl {
+    fn default() -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            seek: AtomicUsize::new(NO_SEEK),
+            finished: AtomicBool::new(false),
+        }
+    }
+}
+
+impl SequencerControl {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed)
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed)
+    }
+
+    /// Requests a jump to the start of the given row, ignored if the row doesn't exist
+    pub fn seek(&self, row: usize) {
+        self.seek.store(row, Ordering::Relaxed)
+    }
+
+    /// Whether the whole score has been played
+    pub fn finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+/// A single oscillator whose volume ramps towards a target one sample at a time
+struct Oscillator {
+    freq: Shared<f64>,
+    pos: Shared<f64>,
+    volume: Shared<f64>,
+    unit: Box<dyn AudioUnit64>,
+    level: f64,
+    target: f64,
+    increment: f64,
+}
+
+impl Oscillator {
+    fn fade_to(&mut self, target: f64, fade_len: usize) {
+        self.target = target;
+        self.increment = (target - self.level).abs() / fade_len.max(1) as f64;
+    }
+
+    /// Starts the waveform again from the beginning, used to strike a new note of a bell or string
+    fn restart(&mut self) {
+        self.unit.reset();
+    }
+
+    fn is_silent(&self) -> bool {
+        self.level == 0.0 && self.target == 0.0
+    }
+
+    fn next_sample(&mut self) -> (f64, f64) {
+        if self.level < self.target {
+            self.level = (self.level + self.increment).min(self.target);
+        } else if self.level > self.target {
+            self.level = (self.level - self.increment).max(self.target);
+        }
+        self.volume.set(self.level);
+        self.unit.get_stereo()
+    }
+}
+
+/// Each voice alternates between two oscillators so a new tone can fade in while the old one fades out
+struct SequencerVoice {
+    oscillators: [Oscillator; 2],
+    current: usize,
+    /// The tone the current oscillator was last asked to play
+    tone: Tone,
+    /// Set for waveforms that die away on their own so every note has to be struck again
+    struck: bool,
+}
+
+impl SequencerVoice {
+    fn play(&mut self, tone: Tone, fade_len: usize) {
+        let current = &mut self.oscillators[self.current];
+        let strike = self.struck && tone.volume > 0.0;
+        // Reusing the oscillator for a repeated pitch avoids two copies of the same wave interfering
+        let repeated = tone.freq == self.tone.freq && tone.pos == self.tone.pos && !strike;
+        if repeated || current.is_silent() {
+            if strike {
+                current.restart();
+            }
+            current.freq.set(tone.freq);
+            current.pos.set(tone.pos);
+            current.fade_to(tone.volume, fade_len);
+        } else {
+            current.fade_to(0.0, fade_len);
+            self.current = 1 - self.current;
+            let next = &mut self.oscillators[self.current];
+            if strike {
+                next.restart();
+            }
+            next.freq.set(tone.freq);
+            next.pos.set(tone.pos);
+            next.fade_to(tone.volume, fade_len);
+        }
+        self.tone = tone;
+    }
+
+    fn next_sample(&mut self) -> (f64, f64) {
+        let [a, b] = &mut self.oscillators;
+        let (a, b) = (a.next_sample(), b.next_sample());
+        (a.0 + b.0, a.1 + b.1)
+    }
+
+    /// How loud the voice is currently playing, including any tone fading out
+    fn level(&self) -> f64 {
+        self.oscillators
+            .iter()
+            .map(|oscillator| oscillator.level)
+            .sum()
+    }
+}
+
+/// Plays a score with changes happening at exact sample offsets
+/// Nothing is allocated after construction so it is safe to run inside an audio callback
+pub struct Sequencer {
+    score: Score,
+    voices: Vec<SequencerVoice>,
+    /// The sample each step starts at followed by the sample the score ends at
+    starts: Vec<usize>,
+    step: usize,
+    sample: usize,
+    fade_len: usize,
+    control: Arc<SequencerControl>,
+}
+
+impl Sequencer {
+    pub fn new(score: Score, sample_rate: f64, control: Arc<SequencerControl>) -> Self {
+        let oscillator = |wave: super::Waveform| {
+            let freq = shared(0.0);
+            let pos = shared(0.0);
+            let volume = shared(0.0);
+            let mut unit = wave.voice(&freq, &pos, &volume);
+            unit.set_sample_rate(sample_rate);
+            unit.allocate();
+            Oscillator {
+                freq,
+                pos,
+                volume,
+                unit,
+                level: 0.0,
+                target: 0.0,
+                increment: 0.0,
+            }
+        };
+        let voices = score
+            .voices
+            .iter()
+            .map(|wave| SequencerVoice {
+                oscillators: [oscillator(*wave), oscillator(*wave)],
+                current: 0,
+                tone: Tone::silent(0.0),
+                struck: wave.is_struck(),
+            })
+            .collect_vec();
+        // Work out each start from the total elapsed time so rounding errors don't accumulate
+        let starts = std::iter::once(0)
+            .chain(score.steps.iter().scan(Duration::ZERO, |elapsed, step| {
+                *elapsed += step.duration;
+                Some((elapsed.as_secs_f64() * sample_rate).round() as usize)
+            }))
+            .collect_vec();
+        let fade_len = (CROSSFADE.as_secs_f64() * sample_rate).round() as usize;
+        let mut sequencer = Self {
+            score,
+            voices,
+            starts,
+            step: 0,
+            sample: 0,
+            fade_len,
+            control,
+        };
+        sequencer.start_step(0);
+        sequencer
+    }
+
+    /// The sample the score ends at, including the final fade out
+    pub fn len(&self) -> usize {
+        self.starts[self.starts.len() - 1] + self.fade_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.score.steps.is_empty()
+    }
+
+    /// Whether the whole score has been played
+    pub fn finished(&self) -> bool {
+        self.control.finished()
+    }
+
+    fn start_step(&mut self, step: usize) {
+        self.step = step;
+        self.sample = self.starts[step];
+        match self.score.steps.get(step) {
+            Some(step) => {
+                for (voice, tone) in self.voices.iter_mut().zip(&step.tones) {
+                    voice.play(*tone, self.fade_len);
+                }
+            }
+            None => {
+                for voice in &mut self.voices {
+                    voice.play(Tone::silent(voice.tone.pos), self.fade_len);
+                }
+            }
+        }
+    }
+
+    pub fn next_sample(&mut self) -> (f64, f64) {
+        let seek = self.control.seek.swap(NO_SEEK, Ordering::Relaxed);
+        if let Some(start) = self.score.rows.get(seek) {
+            self.start_step(*start);
+        }
+        if self.control.paused.load(Ordering::Relaxed) || self.control.finished() {
+            return (0.0, 0.0);
+        }
+        if self.sample >= self.len() {
+            self.control.finished.store(true, Ordering::Relaxed);
+            return (0.0, 0.0);
+        }
+        // Move on once the next step starts, skipping any that are shorter than a sample
+        while self.step < self.score.steps.len() && self.sample >= self.starts[self.step + 1] {
+            self.start_step(self.step + 1);
+        }
+        self.sample += 1;
+        let (left, right) = self
+            .voices
+            .iter_mut()
+            .map(SequencerVoice::next_sample)
+            .fold((0.0, 0.0), |(l, r), (a, b)| (l + a, r + b));
+        // Only the voices that are sounding share the output so a lone tone is as loud as it would be on its own
+        // The levels change smoothly with each fade so the gain does too
+        let level = self.voices.iter().map(SequencerVoice::level).sum::<f64>();
+        let gain = 1.0 / level.max(1.0);
+        (left * gain, right * gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use strum::IntoEnumIterator;
+
+    use crate::audio::{
+        score::{Score, Tone},
+        test_utils::rms,
+        Waveform,
+    };
+
+    const SAMPLE_RATE: f64 = 8000.0;
+
+    fn level(score: &Score) -> f64 {
+        rms(score.render(SAMPLE_RATE).into_iter().map(|(left, _)| left))
+    }
+
+    #[test]
+    fn a_lone_voice_is_as_loud_as_a_score_with_one_voice() {
+        let step = Duration::from_millis(500);
+        let mut single = Score::new(vec![Waveform::Sine]);
+        single.push(step, vec![Tone::new(440.0, 0.0, 1.0)]);
+        let voices = Waveform::iter().collect::<Vec<_>>();
+        let mut many = Score::new(voices.clone());
+        let mut tones = vec![Tone::silent(0.0); voices.len()];
+        tones[0] = Tone::new(440.0, 0.0, 1.0);
+        many.push(step, tones);
+        let (single, many) = (level(&single), level(&many));
+        assert!((single - many).abs() / single < 0.01, "{single} != {many}");
+    }
+
+    #[test]
+    fn voices_sounding_together_stay_within_range() {
+        let mut score = Score::new(vec![Waveform::Sine; 3]);
+        score.push(
+            Duration::from_millis(300),
+            vec![
+                Tone::new(220.0, -1.0, 1.0),
+                Tone::new(330.0, -1.0, 1.0),
+                Tone::new(440.0, -1.0, 1.0),
+            ],
+        );
+        score.push(
+            Duration::from_millis(300),
+            vec![
+                Tone::new(550.0, -1.0, 1.0),
+                Tone::silent(-1.0),
+                Tone::silent(-1.0),
+            ],
+        );
+        let peak = score
+            .render(SAMPLE_RATE)
+            .into_iter()
+            .map(|(left, right)| left.abs().max(right.abs()))
+            .fold(0.0, f64::max);
+        assert!(peak <= 1.0, "peak of {peak}");
+    }
+}
@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use itertools::Itertools;
+use strum::IntoEnumIterator;
+
+use crate::geometry::GeoGeometry;
+
+use super::{
+    indicators::Indicators,
+    low_level::{Playable, Waveform},
+    mapping::PitchMapping,
+    score::{Score, Tone},
+};
+
+/// The length of each note of an earcon
+const NOTE: Duration = Duration::from_millis(90);
+/// The silence after each earcon
+const EARCON_GAP: Duration = Duration::from_millis(200);
+/// The quietest a feature at the edge of the radius is played, relative to the users volume
+const MIN_VOLUME: f64 = 0.15;
+/// Stereo can't tell in front from behind so features to the south are played this much lower
+const BEHIND_PITCH: f64 = 0.5;
+
+/// The short sound that identifies a kind of feature
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Earcon {
+    /// A single high note
+    Point,
+    /// Two notes in the middle of the range
+    Line,
+    /// Three low notes rising
+    Polygon,
+    /// The index of a value of the field used to classify features
+    Class(usize),
+}
+
+impl Earcon {
+    pub fn for_geometry(geometry: &GeoGeometry) -> Self {
+        match geometry {
+            GeoGeometry::Point(_) | GeoGeometry::MultiPoint(_) => Self::Point,
+            GeoGeometry::Line(_) | GeoGeometry::LineString(_) | GeoGeometry::MultiLineString(_) => {
+                Self::Line
+            }
+            GeoGeometry::Polygon(_)
+            | GeoGeometry::MultiPolygon(_)
+            | GeoGeometry::Rect(_)
+            | GeoGeometry::Triangle(_) => Self::Polygon,
+            GeoGeometry::GeometryCollection(geometries) => geometries
+                .iter()
+                .next()
+                .map(Self::for_geometry)
+                .unwrap_or(Self::Point),
+        }
+    }
+
+    fn wave(self) -> Waveform {
+        match self {
+            Self::Point => Waveform::Sine,
+            Self::Line => Waveform::Triangle,
+            Self::Polygon => Waveform::Square,
+            Self::Class(class) => Waveform::iter()
+                .nth(class % Waveform::iter().len())
+                .unwrap(),
+        }
+    }
+
+    fn notes(self, min_freq: f64, max_freq: f64) -> Vec<f64> {
+        let middle = (min_freq * max_freq).sqrt();
+        match self {
+            Self::Point => vec![max_freq],
+            Self::Line => vec![middle, middle],
+            Self::Polygon => vec![min_freq, min_freq * 1.25, min_freq * 1.5],
+            Self::Class(class) => {
+                // Classes that share a waveform are told apart by pitch
+                let notes = PitchMapping::Pentatonic.notes(min_freq, max_freq);
+                let note = notes[(class / Waveform::iter().len()) % notes.len()];
+                vec![note, note]
+            }
+        }
+    }
+}
+
+/// A feature somewhere around the point being explored
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoundscapeItem {
+    pub earcon: Earcon,
+    /// Degrees clockwise from north
+    pub bearing: f64,
+    /// Metres from the point being explored
+    pub distance: f64,
+}
+
+/// Plays an earcon for each feature in turn, panned by its bearing and quieter the further away it is
+#[derive(Clone, Debug, PartialEq)]
+pub struct Soundscape {
+    items: Vec<SoundscapeItem>,
+    radius: f64,
+    min_freq: f64,
+    max_freq: f64,
+    indicators: Indicators,
+}
+
+impl Soundscape {
+    pub fn new(
+        items: Vec<SoundscapeItem>,
+        radius: f64,
+        min_freq: f64,
+        max_freq: f64,
+        indicators: Indicators,
+    ) -> Self {
+        Self {
+            items,
+            radius,
+            min_freq,
+            max_freq,
+            indicators,
+        }
+    }
+}
+
+impl Playable for Soundscape {
+    fn score(&self) -> Score {
+        // Only the waveforms that are heard get a voice so the mix isn't spread over unused ones
+        let voices = self
+            .items
+            .iter()
+            .map(|item| item.earcon.wave())
+            .unique()
+            .collect_vec();
+        let mut score = Score::new(voices.clone());
+        let silence = |pos| vec![Tone::silent(pos); voices.len()];
+        for item in &self.items {
+            score.start_row();
+            // Features to the east are on the right and to the west on the left
+            let pos = item.bearing.to_radians().sin();
+            let pitch = if item.bearing.to_radians().cos() < 0.0 {
+                BEHIND_PITCH
+            } else {
+                1.0
+            };
+            let closeness = 1.0 - (item.distance / self.radius).clamp(0.0, 1.0);
+            let volume = self.indicators.volume * (MIN_VOLUME + (1.0 - MIN_VOLUME) * closeness);
+            let voice = voices
+                .iter()
+                .position(|wave| *wave == item.earcon.wave())
+                .unwrap_or_default();
+            for freq in item.earcon.notes(self.min_freq, self.max_freq) {
+                let mut tones = silence(pos);
+                tones[voice] = Tone::new(freq * pitch, pos, volume);
+                score.push(NOTE, tones);
+            }
+            score.push(EARCON_GAP, silence(pos));
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_freq(bearing: f64) -> f64 {
+        let item = SoundscapeItem {
+            earcon: Earcon::Point,
+            bearing,
+            distance: 0.0,
+        };
+        let soundscape = Soundscape::new(vec![item], 100.0, 220.0, 880.0, Indicators::default());
+        soundscape.score().steps[0].tones[0].freq
+    }
+
+    #[test]
+    fn features_behind_are_played_lower() {
+        assert_eq!(first_freq(0.0), 880.0);
+        assert_eq!(first_freq(180.0), 440.0);
+        // Due east and west stay at the normal pitch
+        assert_eq!(first_freq(90.0), 880.0);
+    }
+
+    #[test]
+    fn only_the_waveforms_heard_get_a_voice() {
+        let items = [Earcon::Point, Earcon::Line, Earcon::Point]
+            .into_iter()
+            .map(|earcon| SoundscapeItem {
+                earcon,
+                bearing: 0.0,
+                distance: 0.0,
+            })
+            .collect();
+        let soundscape = Soundscape::new(items, 100.0, 220.0, 880.0, Indicators::default());
+        assert_eq!(
+            soundscape.score().voices,
+            vec![Waveform::Sine, Waveform::Triangle]
+        );
+    }
+}
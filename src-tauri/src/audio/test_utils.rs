@@ -0,0 +1,18 @@
+//! Helpers for checking rendered audio in tests
+
+/// Estimates the frequency of a periodic signal by counting how often it crosses zero
+pub fn zero_crossing_frequency(samples: &[f64], sample_rate: f64) -> f64 {
+    let crossings = samples
+        .windows(2)
+        .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+        .count();
+    crossings as f64 / 2.0 / (samples.len() as f64 / sample_rate)
+}
+
+/// The root mean square level of a signal
+pub fn rms(samples: impl IntoIterator<Item = f64>) -> f64 {
+    let (sum, len) = samples.into_iter().fold((0.0, 0), |(sum, len), sample| {
+        (sum + sample * sample, len + 1)
+    });
+    (sum / len.max(1) as f64).sqrt()
+}
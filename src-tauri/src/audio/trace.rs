@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use geo::EuclideanLength;
+use itertools::Itertools;
+
+use crate::geometry::{Coord, GeoGeometry, GeoLineString};
+
+use super::{
+    indicators::{different_wave, Indicators},
+    low_level::{Playable, Waveform},
+    mapping::PitchMapping,
+    score::{Score, Tone},
+};
+
+/// How long the click at each vertex lasts
+const VERTEX_CUE: Duration = Duration::from_millis(15);
+/// The longest a single step of the glide between two vertices can be
+const GLIDE_STEP: Duration = Duration::from_millis(20);
+/// The silence between separate lines or rings
+const PATH_GAP: Duration = Duration::from_millis(300);
+
+/// A single line or ring of a geometry
+#[derive(Clone, Debug, PartialEq)]
+pub struct TracePath {
+    pub points: Vec<Coord>,
+    /// Set for the interior rings of polygons
+    pub hole: bool,
+}
+
+/// Breaks a geometry into the lines and rings that make it up
+/// Points have no shape so are skipped
+pub fn trace_paths(geometry: &GeoGeometry) -> Vec<TracePath> {
+    let path = |line: &GeoLineString, hole| TracePath {
+        points: line.0.clone(),
+        hole,
+    };
+    match geometry {
+        GeoGeometry::Point(_) | GeoGeometry::MultiPoint(_) => Vec::new(),
+        GeoGeometry::Line(line) => vec![TracePath {
+            points: vec![line.start, line.end],
+            hole: false,
+        }],
+        GeoGeometry::LineString(line) => vec![path(line, false)],
+        GeoGeometry::MultiLineString(lines) => lines.iter().map(|line| path(line, false)).collect(),
+        GeoGeometry::Polygon(polygon) => std::iter::once(path(polygon.exterior(), false))
+            .chain(polygon.interiors().iter().map(|ring| path(ring, true)))
+            .collect(),
+        GeoGeometry::MultiPolygon(polygons) => polygons
+            .iter()
+            .flat_map(|polygon| trace_paths(&GeoGeometry::Polygon(polygon.clone())))
+            .collect(),
+        GeoGeometry::GeometryCollection(geometries) => {
+            geometries.iter().flat_map(trace_paths).collect()
+        }
+        GeoGeometry::Rect(rect) => trace_paths(&GeoGeometry::Polygon(rect.to_polygon())),
+        GeoGeometry::Triangle(triangle) => {
+            trace_paths(&GeoGeometry::Polygon(triangle.to_polygon()))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceSettings {
+    /// How long it takes to trace every path of the geometry, excluding the gaps between them
+    pub duration: Duration,
+    pub min_freq: f64,
+    pub max_freq: f64,
+    pub wave: Waveform,
+    pub mapping: PitchMapping,
+}
+
+/// Plays the outline of a geometry with pan following x and pitch following y
+/// Holes are played with a different waveform and each vertex is marked with a short click
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeometryTrace {
+    paths: Vec<TracePath>,
+    settings: TraceSettings,
+    indicators: Indicators,
+}
+
+impl GeometryTrace {
+    pub fn new(paths: Vec<TracePath>, settings: TraceSettings, indicators: Indicators) -> Self {
+        Self {
+            paths,
+            settings,
+            indicators,
+        }
+    }
+}
+
+impl Playable for GeometryTrace {
+    fn score(&self) -> Score {
+        let TraceSettings {
+            duration,
+            min_freq,
+            max_freq,
+            wave,
+            mapping,
+        } = self.settings;
+        let hole_wave = different_wave(&[wave]);
+        let cue_wave = different_wave(&[wave, hole_wave]);
+        let mut score = Score::new(vec![wave, hole_wave, cue_wave]);
+        let volume = self.indicators.volume;
+
+        let coords = self.paths.iter().flat_map(|path| &path.points);
+        let (Some((min_x, max_x)), Some((min_y, max_y))) = (
+            coords.clone().map(|coord| coord.x).minmax().into_option(),
+            coords.map(|coord| coord.y).minmax().into_option(),
+        ) else {
+            return score;
+        };
+        let x_range = if max_x > min_x { max_x - min_x } else { 1.0 };
+        let y_range = if max_y > min_y { max_y - min_y } else { 1.0 };
+        let pos = |coord: Coord| (coord.x - min_x) / x_range * 2.0 - 1.0;
+        let freq = |coord: Coord| mapping.freq((coord.y - min_y) / y_range, min_freq, max_freq);
+        // The click sits above the range used for the outline so it stands out
+        let cue_freq = max_freq * 2.0;
+        let tones = |coord: Coord, hole: bool, cue: bool| {
+            let outline = Tone::new(freq(coord), pos(coord), volume);
+            let silent = Tone::silent(pos(coord));
+            let cue = if cue {
+                Tone::new(cue_freq, pos(coord), volume)
+            } else {
+                silent
+            };
+            if hole {
+                vec![silent, outline, cue]
+            } else {
+                vec![outline, silent, cue]
+            }
+        };
+
+        let total_length = self
+            .paths
+            .iter()
+            .map(|path| GeoLineString::new(path.points.clone()).euclidean_length())
+            .sum::<f64>();
+        let segment_count = self
+            .paths
+            .iter()
+            .map(|path| path.points.len().saturating_sub(1))
+            .sum::<usize>();
+        for path in &self.paths {
+            let Some(last) = path.points.last() else {
+                continue;
+            };
+            score.start_row();
+            for (start, end) in path.points.iter().copied().tuple_windows() {
+                // Time is shared out by length so the trace moves at a constant speed
+                let segment = if total_length > 0.0 {
+                    let length = GeoLineString::new(vec![start, end]).euclidean_length();
+                    duration.mul_f64(length / total_length)
+                } else {
+                    duration / segment_count.max(1) as u32
+                };
+                let cue = VERTEX_CUE.min(segment / 2);
+                score.push(cue, tones(start, path.hole, true));
+                let glide = segment - cue;
+                let steps = (glide.as_secs_f64() / GLIDE_STEP.as_secs_f64())
+                    .ceil()
+                    .max(1.0);
+                for step in 1..=steps as usize {
+                    let t = step as f64 / steps;
+                    let coord = Coord {
+                        x: start.x + (end.x - start.x) * t,
+                        y: start.y + (end.y - start.y) * t,
+                    };
+                    score.push(glide.div_f64(steps), tones(coord, path.hole, false));
+                }
+            }
+            score.push(VERTEX_CUE, tones(*last, path.hole, true));
+            score.push(
+                PATH_GAP,
+                vec![Tone::silent(0.0), Tone::silent(0.0), Tone::silent(0.0)],
+            );
+        }
+        score
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// The sample rate used when rendering sonifications to files
+pub const WAV_SAMPLE_RATE: u32 = 44100;
+
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Writes stereo samples to a 16 bit PCM WAV file
+/// Samples outside of -1.0 to 1.0 are clipped
+pub fn write_wav<P: AsRef<Path>>(
+    path: P,
+    sample_rate: u32,
+    samples: &[(f64, f64)],
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_wav_to(&mut file, sample_rate, samples)?;
+    file.flush()
+}
+
+/// Writes stereo samples as a 16 bit PCM WAV to anything that can be written to
+pub fn write_wav_to<W: Write>(
+    file: &mut W,
+    sample_rate: u32,
+    samples: &[(f64, f64)],
+) -> io::Result<()> {
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let byte_rate = sample_rate * block_align as u32;
+    let data_len = samples.len() as u32 * block_align as u32;
+
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_len).to_le_bytes())?;
+    file.write_all(b"WAVE")?;
+
+    file.write_all(b"fmt ")?;
+    file.write_all(&16_u32.to_le_bytes())?;
+    // PCM
+    file.write_all(&1_u16.to_le_bytes())?;
+    file.write_all(&CHANNELS.to_le_bytes())?;
+    file.write_all(&sample_rate.to_le_bytes())?;
+    file.write_all(&byte_rate.to_le_bytes())?;
+    file.write_all(&block_align.to_le_bytes())?;
+    file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+    file.write_all(b"data")?;
+    file.write_all(&data_len.to_le_bytes())?;
+    for (left, right) in samples {
+        for sample in [left, right] {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f64) as i16;
+            file.write_all(&sample.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::audio::{
+        score::{Score, Tone},
+        test_utils::zero_crossing_frequency,
+        Waveform,
+    };
+
+    const HEADER_LEN: usize = 44;
+    const STEP: Duration = Duration::from_millis(200);
+
+    fn left_channel(wav: &[u8]) -> Vec<f64> {
+        wav[HEADER_LEN..]
+            .chunks_exact(4)
+            .map(|frame| i16::from_le_bytes([frame[0], frame[1]]) as f64 / i16::MAX as f64)
+            .collect()
+    }
+
+    #[test]
+    fn renders_each_step_at_its_frequency() {
+        let freqs = [440.0, 880.0, 330.0];
+        let mut score = Score::new(vec![Waveform::Sine]);
+        for freq in freqs {
+            score.push(STEP, vec![Tone::new(freq, 0.0, 1.0)]);
+        }
+        let samples = score.render(WAV_SAMPLE_RATE as f64);
+        let step_len = (STEP.as_secs_f64() * WAV_SAMPLE_RATE as f64) as usize;
+        // The last step fades out after the score ends
+        let fade_len = (0.005 * WAV_SAMPLE_RATE as f64).round() as usize;
+        assert_eq!(samples.len(), step_len * freqs.len() + fade_len);
+
+        let mut wav = Vec::new();
+        write_wav_to(&mut wav, WAV_SAMPLE_RATE, &samples).unwrap();
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav.len(), HEADER_LEN + samples.len() * 4);
+        // The data chunk holds the score and its fade out with nothing after
+        let data_len = u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize;
+        assert_eq!(data_len, (step_len * freqs.len() + fade_len) * 4);
+
+        let left = left_channel(&wav);
+        for (index, freq) in freqs.into_iter().enumerate() {
+            // Skip the crossfades at either end of the step
+            let step = &left[index * step_len + fade_len..(index + 1) * step_len - fade_len];
+            let measured = zero_crossing_frequency(step, WAV_SAMPLE_RATE as f64);
+            assert!(
+                (measured - freq).abs() / freq < 0.02,
+                "step {index} played at {measured}Hz instead of {freq}Hz"
+            );
+        }
+    }
+
+    #[test]
+    fn clips_samples_outside_the_valid_range() {
+        let mut wav = Vec::new();
+        write_wav_to(&mut wav, WAV_SAMPLE_RATE, &[(2.0, -2.0)]).unwrap();
+        assert_eq!(left_channel(&wav), vec![1.0]);
+        let right = i16::from_le_bytes([wav[HEADER_LEN + 2], wav[HEADER_LEN + 3]]);
+        assert_eq!(right, -i16::MAX);
+    }
+}
@@ -0,0 +1,773 @@
+//! A small map algebra language for doing maths on raster bands
+//! For example `(B2 - B1) / (B2 + B1)` or `where(dem > 500, slope, nodata)`
+//! No data is carried through as NaN so any calculation involving it gives no data
+
+use std::fmt::Display;
+
+use itertools::Itertools;
+use ndarray::{Array2, Zip};
+
+/// A band used in an expression
+/// `B2` is band 2 of the current dataset, `dem` is band 1 of the dataset named dem and `dem.B2` is its second band
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BandRef {
+    pub dataset: Option<String>,
+    pub band: usize,
+}
+
+impl Display for BandRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.dataset {
+            Some(dataset) => write!(f, "{dataset}.B{}", self.band),
+            None => write!(f, "B{}", self.band),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// How tightly the operator binds and whether it groups from the right
+    fn precedence(self) -> (u8, bool) {
+        match self {
+            Self::Or => (1, false),
+            Self::And => (2, false),
+            Self::Equal | Self::NotEqual => (3, false),
+            Self::Less | Self::LessOrEqual | Self::Greater | Self::GreaterOrEqual => (4, false),
+            Self::Add | Self::Subtract => (5, false),
+            Self::Multiply | Self::Divide | Self::Remainder => (6, false),
+            Self::Power => (8, true),
+        }
+    }
+
+    fn apply(self, a: f64, b: f64) -> f64 {
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+        // Comparisons would otherwise turn no data into a valid 0
+        if a.is_nan() || b.is_nan() {
+            return f64::NAN;
+        }
+        match self {
+            Self::Add => a + b,
+            Self::Subtract => a - b,
+            Self::Multiply => a * b,
+            Self::Divide => a / b,
+            Self::Remainder => a % b,
+            Self::Power => a.powf(b),
+            Self::Less => truth(a < b),
+            Self::LessOrEqual => truth(a <= b),
+            Self::Greater => truth(a > b),
+            Self::GreaterOrEqual => truth(a >= b),
+            Self::Equal => truth(a == b),
+            Self::NotEqual => truth(a != b),
+            Self::And => truth(a != 0.0 && b != 0.0),
+            Self::Or => truth(a != 0.0 || b != 0.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Floor,
+    Ceil,
+    Round,
+    /// 1 where the value is no data and 0 everywhere else
+    IsNoData,
+    Min,
+    Max,
+    Pow,
+    Atan2,
+    /// where(condition, then, otherwise)
+    Where,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_str() {
+            "abs" => Self::Abs,
+            "sqrt" => Self::Sqrt,
+            "exp" => Self::Exp,
+            "ln" | "log" => Self::Ln,
+            "log10" => Self::Log10,
+            "sin" => Self::Sin,
+            "cos" => Self::Cos,
+            "tan" => Self::Tan,
+            "asin" => Self::Asin,
+            "acos" => Self::Acos,
+            "atan" => Self::Atan,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            "round" => Self::Round,
+            "isnodata" => Self::IsNoData,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "pow" => Self::Pow,
+            "atan2" => Self::Atan2,
+            "where" | "if" => Self::Where,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Min | Self::Max | Self::Pow | Self::Atan2 => 2,
+            Self::Where => 3,
+            _ => 1,
+        }
+    }
+
+    fn apply_unary(self, value: f64) -> f64 {
+        match self {
+            Self::Abs => value.abs(),
+            Self::Sqrt => value.sqrt(),
+            Self::Exp => value.exp(),
+            Self::Ln => value.ln(),
+            Self::Log10 => value.log10(),
+            Self::Sin => value.sin(),
+            Self::Cos => value.cos(),
+            Self::Tan => value.tan(),
+            Self::Asin => value.asin(),
+            Self::Acos => value.acos(),
+            Self::Atan => value.atan(),
+            Self::Floor => value.floor(),
+            Self::Ceil => value.ceil(),
+            Self::Round => value.round(),
+            Self::IsNoData => {
+                if value.is_nan() {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            _ => unreachable!("{self:?} takes more than one argument"),
+        }
+    }
+
+    fn apply_binary(self, a: f64, b: f64) -> f64 {
+        match self {
+            // f64::min and max ignore NaN but no data should win
+            _ if a.is_nan() || b.is_nan() => f64::NAN,
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+            Self::Pow => a.powf(b),
+            Self::Atan2 => a.atan2(b),
+            _ => unreachable!("{self:?} doesn't take two arguments"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    NoData,
+    Band(BandRef),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Binary(BinaryOp),
+    Not,
+    Open,
+    Close,
+    Comma,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars = expression.char_indices().collect_vec();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(&(position, c)) = chars.get(i) {
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('<', Some('=')) => (Token::Binary(BinaryOp::LessOrEqual), 2),
+            ('>', Some('=')) => (Token::Binary(BinaryOp::GreaterOrEqual), 2),
+            ('=', Some('=')) => (Token::Binary(BinaryOp::Equal), 2),
+            ('!', Some('=')) => (Token::Binary(BinaryOp::NotEqual), 2),
+            ('&', Some('&')) => (Token::Binary(BinaryOp::And), 2),
+            ('|', Some('|')) => (Token::Binary(BinaryOp::Or), 2),
+            ('*', Some('*')) => (Token::Binary(BinaryOp::Power), 2),
+            ('<', _) => (Token::Binary(BinaryOp::Less), 1),
+            ('>', _) => (Token::Binary(BinaryOp::Greater), 1),
+            ('+', _) => (Token::Binary(BinaryOp::Add), 1),
+            ('-', _) => (Token::Binary(BinaryOp::Subtract), 1),
+            ('*', _) => (Token::Binary(BinaryOp::Multiply), 1),
+            ('/', _) => (Token::Binary(BinaryOp::Divide), 1),
+            ('%', _) => (Token::Binary(BinaryOp::Remainder), 1),
+            ('^', _) => (Token::Binary(BinaryOp::Power), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            (',', _) => (Token::Comma, 1),
+            (c, _) if c.is_ascii_digit() || c == '.' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|(_, c)| c.is_ascii_digit() || *c == '.')
+                    .count();
+                // Allow exponents like 1e3, 1e+3 and 1e-3
+                let exponent = match chars.get(i + len..) {
+                    Some([(_, 'e' | 'E'), (_, '+' | '-'), (_, c), ..]) if c.is_ascii_digit() => 2,
+                    Some([(_, 'e' | 'E'), (_, c), ..]) if c.is_ascii_digit() => 1,
+                    _ => 0,
+                };
+                let len = if exponent == 0 {
+                    len
+                } else {
+                    len + exponent
+                        + chars[i + len + exponent..]
+                            .iter()
+                            .take_while(|(_, c)| c.is_ascii_digit())
+                            .count()
+                };
+                let text = chars[i..i + len].iter().map(|(_, c)| c).collect::<String>();
+                let number = text
+                    .parse()
+                    .map_err(|_| format!("{text} at position {position} is not a valid number"))?;
+                (Token::Number(number), len)
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '.')
+                    .count();
+                let text = chars[i..i + len].iter().map(|(_, c)| c).collect::<String>();
+                let token = match text.to_lowercase().as_str() {
+                    "and" => Token::Binary(BinaryOp::And),
+                    "or" => Token::Binary(BinaryOp::Or),
+                    "not" => Token::Not,
+                    _ => Token::Ident(text),
+                };
+                (token, len)
+            }
+            (c, _) => return Err(format!("Unexpected {c} at position {position}")),
+        };
+        tokens.push((position, token));
+        i += len;
+    }
+    Ok(tokens)
+}
+
+/// Reads B followed by a band number, like B2
+fn parse_band_name(name: &str) -> Option<usize> {
+    let number = name.strip_prefix('B').or_else(|| name.strip_prefix('b'))?;
+    number.parse().ok().filter(|band| *band > 0)
+}
+
+fn parse_band(name: &str) -> BandRef {
+    if let Some(band) = parse_band_name(name) {
+        return BandRef {
+            dataset: None,
+            band,
+        };
+    }
+    if let Some((dataset, band)) = name.rsplit_once('.') {
+        if let Some(band) = parse_band_name(band) {
+            return BandRef {
+                dataset: Some(dataset.to_string()),
+                band,
+            };
+        }
+    }
+    BandRef {
+        dataset: Some(name.to_string()),
+        band: 1,
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.len)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).map(|(_, token)| token.clone());
+        self.index += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, description: &str) -> Result<(), String> {
+        let position = self.position();
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(format!("Expected {description} at position {position}")),
+        }
+    }
+
+    fn expression(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Binary(op)) = self.peek() {
+            let op = *op;
+            let (precedence, right_associative) = op.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+            self.next();
+            let next_precedence = if right_associative {
+                precedence
+            } else {
+                precedence + 1
+            };
+            let right = self.expression(next_precedence)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Binary(BinaryOp::Subtract)) => {
+                self.next();
+                // Binds looser than ^ so -2^2 is -4
+                let operand = self.expression(BinaryOp::Power.precedence().0)?;
+                Ok(Expr::Unary(UnaryOp::Negate, Box::new(operand)))
+            }
+            Some(Token::Binary(BinaryOp::Add)) => {
+                self.next();
+                self.unary()
+            }
+            Some(Token::Not) => {
+                self.next();
+                let operand = self.expression(BinaryOp::Equal.precedence().0)?;
+                Ok(Expr::Unary(UnaryOp::Not, Box::new(operand)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Open) => {
+                let expr = self.expression(0)?;
+                self.expect(Token::Close, "a closing bracket")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::Open) => {
+                let function = Function::from_name(&name)
+                    .ok_or_else(|| format!("Unknown function {name} at position {position}"))?;
+                self.next();
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::Close) {
+                    loop {
+                        args.push(self.expression(0)?);
+                        if self.peek() != Some(&Token::Comma) {
+                            break;
+                        }
+                        self.next();
+                    }
+                }
+                self.expect(Token::Close, "a closing bracket")?;
+                if args.len() != function.arity() {
+                    return Err(format!(
+                        "{name} at position {position} takes {} arguments but was given {}",
+                        function.arity(),
+                        args.len()
+                    ));
+                }
+                Ok(Expr::Call(function, args))
+            }
+            Some(Token::Ident(name)) => Ok(match name.to_lowercase().as_str() {
+                "nodata" | "nan" => Expr::NoData,
+                "pi" => Expr::Number(std::f64::consts::PI),
+                _ => Expr::Band(parse_band(&name)),
+            }),
+            Some(_) => Err(format!("Unexpected symbol at position {position}")),
+            None => Err("The expression ended unexpectedly".to_string()),
+        }
+    }
+}
+
+/// Parses a map algebra expression
+pub fn parse(expression: &str) -> Result<Expr, String> {
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Err("The expression is empty".to_string());
+    }
+    let mut parser = Parser {
+        tokens,
+        index: 0,
+        len: expression.len(),
+    };
+    let expr = parser.expression(0)?;
+    if parser.index < parser.tokens.len() {
+        return Err(format!(
+            "Unexpected symbol at position {}",
+            parser.position()
+        ));
+    }
+    Ok(expr)
+}
+
+/// Either a single number or a whole grid of them
+enum Value {
+    Scalar(f64),
+    Grid(Array2<f64>),
+}
+
+impl Value {
+    fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        match self {
+            Self::Scalar(value) => Self::Scalar(f(value)),
+            Self::Grid(grid) => Self::Grid(grid.mapv_into(f)),
+        }
+    }
+
+    fn zip(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self {
+        match (self, other) {
+            (Self::Scalar(a), Self::Scalar(b)) => Self::Scalar(f(a, b)),
+            (Self::Grid(a), Self::Scalar(b)) => Self::Grid(a.mapv_into(|a| f(a, b))),
+            (Self::Scalar(a), Self::Grid(b)) => Self::Grid(b.mapv_into(|b| f(a, b))),
+            (Self::Grid(a), Self::Grid(b)) => {
+                Self::Grid(Zip::from(&a).and(&b).map_collect(|a, b| f(*a, *b)))
+            }
+        }
+    }
+
+    fn at(&self, index: (usize, usize)) -> f64 {
+        match self {
+            Self::Scalar(value) => *value,
+            Self::Grid(grid) => grid[index],
+        }
+    }
+
+    fn shape(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Scalar(_) => None,
+            Self::Grid(grid) => Some(grid.dim()),
+        }
+    }
+}
+
+impl Expr {
+    /// Every band the expression reads, each listed once
+    pub fn bands(&self) -> Vec<&BandRef> {
+        let mut bands = Vec::new();
+        self.collect_bands(&mut bands);
+        bands.into_iter().unique().collect()
+    }
+
+    fn collect_bands<'a>(&'a self, bands: &mut Vec<&'a BandRef>) {
+        match self {
+            Expr::Number(_) | Expr::NoData => {}
+            Expr::Band(band) => bands.push(band),
+            Expr::Unary(_, operand) => operand.collect_bands(bands),
+            Expr::Binary(_, left, right) => {
+                left.collect_bands(bands);
+                right.collect_bands(bands);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|arg| arg.collect_bands(bands)),
+        }
+    }
+
+    /// Evaluates the expression with each band looked up by the given function
+    /// The grids must have no data already replaced with NaN and it is an error if they aren't all the same shape
+    /// Results that aren't finite, like dividing by zero, are no data
+    pub fn evaluate<'a, F>(&self, band: &F) -> Result<Array2<f64>, String>
+    where
+        F: Fn(&BandRef) -> Option<&'a Array2<f64>>,
+    {
+        let mut first = None;
+        for band_ref in self.bands() {
+            let grid = band(band_ref).ok_or_else(|| format!("Couldn't find band {band_ref}"))?;
+            match first {
+                None => first = Some((band_ref, grid.dim())),
+                Some((first, (rows, cols))) if grid.dim() != (rows, cols) => {
+                    return Err(format!(
+                        "{band_ref} is {} by {} pixels but {first} is {cols} by {rows}",
+                        grid.ncols(),
+                        grid.nrows()
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        match self.evaluate_value(band)? {
+            Value::Grid(grid) => {
+                Ok(grid.mapv_into(|value| if value.is_finite() { value } else { f64::NAN }))
+            }
+            Value::Scalar(_) => Err("The expression doesn't use any bands".to_string()),
+        }
+    }
+
+    fn evaluate_value<'a, F>(&self, band: &F) -> Result<Value, String>
+    where
+        F: Fn(&BandRef) -> Option<&'a Array2<f64>>,
+    {
+        Ok(match self {
+            Expr::Number(number) => Value::Scalar(*number),
+            Expr::NoData => Value::Scalar(f64::NAN),
+            Expr::Band(band_ref) => Value::Grid(
+                band(band_ref)
+                    .ok_or_else(|| format!("Couldn't find band {band_ref}"))?
+                    .clone(),
+            ),
+            Expr::Unary(UnaryOp::Negate, operand) => operand.evaluate_value(band)?.map(|x| -x),
+            Expr::Unary(UnaryOp::Not, operand) => operand.evaluate_value(band)?.map(|x| {
+                if x.is_nan() {
+                    x
+                } else if x == 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }),
+            Expr::Binary(op, left, right) => left
+                .evaluate_value(band)?
+                .zip(right.evaluate_value(band)?, |a, b| op.apply(a, b)),
+            Expr::Call(Function::Where, args) => {
+                let condition = args[0].evaluate_value(band)?;
+                let then = args[1].evaluate_value(band)?;
+                let otherwise = args[2].evaluate_value(band)?;
+                let choose = |condition: f64, then: f64, otherwise: f64| {
+                    if condition.is_nan() {
+                        f64::NAN
+                    } else if condition != 0.0 {
+                        then
+                    } else {
+                        otherwise
+                    }
+                };
+                match [&condition, &then, &otherwise]
+                    .into_iter()
+                    .find_map(Value::shape)
+                {
+                    None => Value::Scalar(choose(
+                        condition.at((0, 0)),
+                        then.at((0, 0)),
+                        otherwise.at((0, 0)),
+                    )),
+                    Some(shape) => Value::Grid(Array2::from_shape_fn(shape, |index| {
+                        choose(condition.at(index), then.at(index), otherwise.at(index))
+                    })),
+                }
+            }
+            Expr::Call(function, args) if function.arity() == 2 => args[0]
+                .evaluate_value(band)?
+                .zip(args[1].evaluate_value(band)?, |a, b| {
+                    function.apply_binary(a, b)
+                }),
+            Expr::Call(function, args) => args[0]
+                .evaluate_value(band)?
+                .map(|value| function.apply_unary(value)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ndarray::array;
+
+    use super::*;
+
+    /// Evaluates an expression against the given bands with NaN turned into None so results can be compared
+    fn evaluate(
+        expression: &str,
+        bands: &[(&str, Array2<f64>)],
+    ) -> Result<Vec<Option<f64>>, String> {
+        let bands = bands
+            .iter()
+            .map(|(name, grid)| (parse_band(name), grid.clone()))
+            .collect::<HashMap<_, _>>();
+        let result = parse(expression)?.evaluate(&|band_ref| bands.get(band_ref))?;
+        Ok(result
+            .iter()
+            .map(|value| (!value.is_nan()).then_some(*value))
+            .collect())
+    }
+
+    /// Evaluates an expression that only uses numbers
+    /// Whole expressions need a band so one holding 0 is added on
+    fn value(expression: &str) -> f64 {
+        let result = evaluate(&format!("B1 + ({expression})"), &[("B1", array![[0.0]])]).unwrap();
+        result[0].unwrap_or(f64::NAN)
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(value("-2^2"), -4.0);
+        assert_eq!(value("2^3^2"), 512.0);
+        assert_eq!(value("2**3"), 8.0);
+        assert_eq!(value("1 + 2 * 3"), 7.0);
+        assert_eq!(value("(1 + 2) * 3"), 9.0);
+        assert_eq!(value("8 - 4 - 2"), 2.0);
+        assert_eq!(value("8 / 4 / 2"), 1.0);
+        assert_eq!(value("7 % 4 * 2"), 6.0);
+    }
+
+    #[test]
+    fn bands_bind_like_numbers() {
+        let bands = [
+            ("a", array![[1.0, 2.0]]),
+            ("b", array![[3.0, 4.0]]),
+            ("c", array![[5.0, 6.0]]),
+        ];
+        assert_eq!(
+            evaluate("a + b * c", &bands),
+            Ok(vec![Some(16.0), Some(26.0)])
+        );
+        assert_eq!(
+            evaluate("(a + b) * c", &bands),
+            Ok(vec![Some(20.0), Some(36.0)])
+        );
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(value("1e3"), 1000.0);
+        assert_eq!(value("1e+3"), 1000.0);
+        assert_eq!(value("1e-3"), 0.001);
+        assert_eq!(value("2.5E2"), 250.0);
+        assert_eq!(value(".5"), 0.5);
+    }
+
+    #[test]
+    fn comparison_and_logical_operators() {
+        assert_eq!(value("1 < 2"), 1.0);
+        assert_eq!(value("2 <= 1"), 0.0);
+        assert_eq!(value("2 > 1"), 1.0);
+        assert_eq!(value("1 >= 1"), 1.0);
+        assert_eq!(value("3 == 3"), 1.0);
+        assert_eq!(value("1 != 1"), 0.0);
+        assert_eq!(value("1 && 0"), 0.0);
+        assert_eq!(value("1 || 0"), 1.0);
+        assert_eq!(value("!0"), 1.0);
+        assert_eq!(value("not 2"), 0.0);
+        assert_eq!(value("1 < 2 and 3 > 4 or 1"), 1.0);
+        assert_eq!(value("1 + 1 == 2"), 1.0);
+    }
+
+    #[test]
+    fn where_picks_per_pixel() {
+        let bands = [("a", array![[1.0, 5.0], [3.0, 0.0]])];
+        assert_eq!(
+            evaluate("where(a > 2, a, 0)", &bands),
+            Ok(vec![Some(0.0), Some(5.0), Some(3.0), Some(0.0)])
+        );
+        assert_eq!(
+            evaluate("if(a, 1, nodata)", &bands),
+            Ok(vec![Some(1.0), Some(1.0), Some(1.0), None])
+        );
+    }
+
+    #[test]
+    fn no_data_propagates() {
+        let bands = [("a", array![[1.0, f64::NAN]])];
+        assert_eq!(evaluate("a + 1", &bands), Ok(vec![Some(2.0), None]));
+        assert_eq!(evaluate("a > 0", &bands), Ok(vec![Some(1.0), None]));
+        assert_eq!(evaluate("!a", &bands), Ok(vec![Some(0.0), None]));
+        assert_eq!(evaluate("min(a, 5)", &bands), Ok(vec![Some(1.0), None]));
+        assert_eq!(
+            evaluate("where(a > 0, 1, 2)", &bands),
+            Ok(vec![Some(1.0), None])
+        );
+        assert_eq!(
+            evaluate("isnodata(a)", &bands),
+            Ok(vec![Some(0.0), Some(1.0)])
+        );
+        assert_eq!(evaluate("a + nodata", &bands), Ok(vec![None, None]));
+        // Results that aren't finite are no data too
+        assert_eq!(evaluate("a / 0", &bands), Ok(vec![None, None]));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse("(1 + 2"),
+            Err("Expected a closing bracket at position 6".to_string())
+        );
+        assert_eq!(
+            parse("1 + 2)"),
+            Err("Unexpected symbol at position 5".to_string())
+        );
+        assert_eq!(
+            parse("1 2"),
+            Err("Unexpected symbol at position 2".to_string())
+        );
+        assert_eq!(
+            parse("foo(1)"),
+            Err("Unknown function foo at position 0".to_string())
+        );
+        assert_eq!(
+            parse("min(1)"),
+            Err("min at position 0 takes 2 arguments but was given 1".to_string())
+        );
+        assert_eq!(parse(" "), Err("The expression is empty".to_string()));
+        assert_eq!(
+            parse("1 +"),
+            Err("The expression ended unexpectedly".to_string())
+        );
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        let bands = [("a", array![[1.0, 2.0]]), ("b", array![[1.0], [2.0]])];
+        assert_eq!(
+            evaluate("a + missing", &bands),
+            Err("Couldn't find band missing.B1".to_string())
+        );
+        assert_eq!(
+            evaluate("a + b", &bands),
+            Err("b.B1 is 1 by 2 pixels but a.B1 is 2 by 1".to_string())
+        );
+        assert_eq!(
+            evaluate("1 + 2", &bands),
+            Err("The expression doesn't use any bands".to_string())
+        );
+    }
+}
@@ -9,8 +9,10 @@ use ndarray::Array2;
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::audio::graph::{play_rasta, RasterGraphSettings};
-use crate::audio::{histogram::play_histogram, Waveform};
+use crate::audio::graph::{Aggregation, RasterGraph, RasterGraphSettings, ScanOrder};
+use crate::audio::histogram::{AudioHistogram, HistogramSettings};
+use crate::audio::mapping::PitchMapping;
+use crate::audio::{low_level::Playable, Waveform};
 use crate::gdal_if::read_raster_data;
 
 #[derive(Parser, Debug)]
@@ -36,6 +38,43 @@ pub struct HistogramArgs {
     wave: WaveType,
 }
 
+#[derive(Debug, Args, Clone, Default, Serialize, Deserialize)]
+pub struct OutputArgs {
+    /// Write the sonification to a WAV file instead of playing it
+    /// Files ending in .mid or .midi are written as MIDI instead
+    #[arg(short, long)]
+    #[serde(default)]
+    output: Option<PathBuf>,
+}
+
+impl OutputArgs {
+    fn play(&self, sonification: impl Playable) {
+        let Some(output) = &self.output else {
+            sonification.play();
+            return;
+        };
+        let is_midi = output
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("mid") || extension.eq_ignore_ascii_case("midi")
+            });
+        let result = if is_midi {
+            sonification.save_midi(output)
+        } else {
+            sonification.save_wav(output)
+        };
+        if let Err(err) = result {
+            eprint!(
+                "Failed to write audio to {}: {}",
+                output.to_string_lossy(),
+                err
+            );
+            exit(-1)
+        }
+    }
+}
+
 fn default_band() -> usize {
     1
 }
@@ -48,6 +87,14 @@ pub struct FrequencyArgs {
     #[arg(long, default_value_t = 2048.0)]
     #[serde(default = "default_max_freq")]
     max_freq: f64,
+    /// How values are mapped to pitches, one of linear, logarithmic, semitones, pentatonic or major
+    #[arg(long, default_value_t = PitchMapping::Linear)]
+    #[serde(default)]
+    mapping: PitchMapping,
+    /// Play reference tones for the lowest, highest and some values in between before playback
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    legend: bool,
 }
 
 fn default_min_freq() -> f64 {
@@ -75,6 +122,22 @@ pub struct GlobalGraphArgs {
     #[arg(long, default_value_t = false)]
     #[serde(default)]
     classified: bool,
+    /// Play each class with its own waveform at a fixed pitch, a legend of the classes is played first
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    categorical: bool,
+    /// The order cells are played in, one of rows, boustrophedon, columns or spiral
+    #[arg(long, default_value_t = ScanOrder::Rows)]
+    #[serde(default)]
+    scan_order: ScanOrder,
+    /// How the pixels in each cell are combined, one of mean, median, min, max, mode or a percentile such as p90
+    #[arg(long, default_value_t = Aggregation::Mean)]
+    #[serde(default)]
+    aggregation: Aggregation,
+    /// Fill cells without data from their neighbours
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    fill_gaps: bool,
 }
 fn default_rows() -> usize {
     10
@@ -103,10 +166,18 @@ pub struct IndividualGraphArgs {
     #[serde(flatten)]
     pub global: GlobalGraphArgs,
 }
+/// Plays several bands of the same dataset at once, each with its own waveform
 #[derive(Debug, Args)]
 pub struct MultiBandArgs {
-    #[arg(global = true)]
-    pub band: BandWave,
+    #[arg()]
+    pub name: String,
+    /// The bands to play and the wave to play each with, for example 3=sine 4=square
+    #[arg(required = true, num_args = 1..)]
+    pub bands: Vec<BandWave>,
+    #[command(flatten)]
+    pub freq_settings: FrequencyArgs,
+    #[command(flatten)]
+    pub global: GlobalGraphArgs,
 }
 
 #[derive(Debug, Clone)]
@@ -139,8 +210,25 @@ enum AllCommands {
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    Graph(IndividualGraphArgs),
-    Histogram(HistogramArgs),
+    Graph {
+        #[command(flatten)]
+        graph: IndividualGraphArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    Histogram {
+        #[command(flatten)]
+        histogram: HistogramArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    #[command(name = "multiband")]
+    MultiBand {
+        #[command(flatten)]
+        bands: MultiBandArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -153,6 +241,9 @@ enum JsonCommands {
 pub struct JsonArgs {
     #[arg()]
     payload: JsonCommands,
+    #[command(flatten)]
+    #[serde(flatten)]
+    output: OutputArgs,
 }
 
 impl FromStr for JsonCommands {
@@ -175,12 +266,13 @@ pub struct GraphArgs {
 pub fn launch_commandline_app(args: Input) {
     match args.command {
         AllCommands::Commands(cmd) => match cmd {
-            Commands::Graph(args) => run_single_graph(args),
-            Commands::Histogram(args) => run_histogram(args),
+            Commands::Graph { graph, output } => run_single_graph(graph, output),
+            Commands::Histogram { histogram, output } => run_histogram(histogram, output),
+            Commands::MultiBand { bands, output } => run_multiband(bands, output),
         },
-        AllCommands::Json(JsonArgs { payload }) => match payload {
-            JsonCommands::Histogram(args) => run_histogram(args),
-            JsonCommands::Graph(args) => run_multiple_graph(args),
+        AllCommands::Json(JsonArgs { payload, output }) => match payload {
+            JsonCommands::Histogram(args) => run_histogram(args, output),
+            JsonCommands::Graph(args) => run_multiple_graph(args, output),
         },
     }
 }
@@ -193,6 +285,11 @@ pub enum WaveType {
     Triangle,
     Square,
     Sawtooth,
+    WhiteNoise,
+    PinkNoise,
+    Bell,
+    Pluck,
+    Organ,
 }
 
 impl FromStr for WaveType {
@@ -203,6 +300,11 @@ impl FromStr for WaveType {
             "triangle" => Ok(Self::Triangle),
             "square" => Ok(Self::Square),
             "sawtooth" => Ok(Self::Sawtooth),
+            "white_noise" | "white" => Ok(Self::WhiteNoise),
+            "pink_noise" | "pink" => Ok(Self::PinkNoise),
+            "bell" => Ok(Self::Bell),
+            "pluck" => Ok(Self::Pluck),
+            "organ" => Ok(Self::Organ),
             _ => Err("Not a valid wave type"),
         }
     }
@@ -215,6 +317,11 @@ impl Display for WaveType {
             Self::Triangle => f.write_str("triangle"),
             Self::Square => f.write_str("square"),
             Self::Sawtooth => f.write_str("sawtooth"),
+            Self::WhiteNoise => f.write_str("white_noise"),
+            Self::PinkNoise => f.write_str("pink_noise"),
+            Self::Bell => f.write_str("bell"),
+            Self::Pluck => f.write_str("pluck"),
+            Self::Organ => f.write_str("organ"),
         }
     }
 }
@@ -226,11 +333,16 @@ impl From<WaveType> for Waveform {
             WaveType::Triangle => Self::Triangle,
             WaveType::Square => Self::Square,
             WaveType::Sawtooth => Self::Sawtooth,
+            WaveType::WhiteNoise => Self::WhiteNoise,
+            WaveType::PinkNoise => Self::PinkNoise,
+            WaveType::Bell => Self::Bell,
+            WaveType::Pluck => Self::Pluck,
+            WaveType::Organ => Self::Organ,
         }
     }
 }
 
-fn run_histogram(args: HistogramArgs) {
+fn run_histogram(args: HistogramArgs, output: OutputArgs) {
     let name = args.name;
     let Ok(dataset) = Dataset::open(&name) else {
         eprint!("Failed to read dataset at {}", name.to_string_lossy());
@@ -253,7 +365,16 @@ fn run_histogram(args: HistogramArgs) {
     };
     let wave: Waveform = args.wave.into();
     let counts = histogram.counts().iter().map(|x| (*x) as f64).collect_vec();
-    play_histogram(counts, Default::default(), wave);
+    let settings = HistogramSettings {
+        mapping: args.freq_settings.mapping,
+        legend: args.freq_settings.legend,
+        ..Default::default()
+    };
+    let histogram = AudioHistogram::new(counts, settings, wave, Default::default());
+    for line in histogram.legend_text() {
+        println!("{line}");
+    }
+    output.play(histogram);
 }
 
 fn gen_graph_options(
@@ -285,20 +406,49 @@ fn gen_graph_options(
         rows: args.global.rows,
         cols: args.global.columns,
         classified: args.global.classified,
+        categorical: args.global.categorical,
         wave,
         min_value: None,
         max_value: None,
+        mapping: args.freq_settings.mapping,
+        legend: args.freq_settings.legend,
+        scan_order: args.global.scan_order,
+        aggregation: args.global.aggregation,
+        fill_gaps: args.global.fill_gaps,
     };
     (data, min, max, no_data_value, settings)
 }
 
-fn run_single_graph(args: IndividualGraphArgs) {
+fn run_single_graph(args: IndividualGraphArgs, output: OutputArgs) {
     let vals = gen_graph_options(args);
-    play_rasta(vec![vals]);
+    play_graph(RasterGraph::new(vec![vals], Default::default()), output);
 }
-fn run_multiple_graph(args: Vec<IndividualGraphArgs>) {
+fn run_multiple_graph(args: Vec<IndividualGraphArgs>, output: OutputArgs) {
     let vals = args.into_iter().map(gen_graph_options).collect();
-    play_rasta(vals);
+    play_graph(RasterGraph::new(vals, Default::default()), output);
+}
+
+/// Prints the legend so it can be read along with the sounds before the graph plays
+fn play_graph(graph: RasterGraph, output: OutputArgs) {
+    for line in graph.legend_text() {
+        println!("{line}");
+    }
+    output.play(graph);
+}
+
+fn run_multiband(args: MultiBandArgs, output: OutputArgs) {
+    let graphs = args
+        .bands
+        .into_iter()
+        .map(|BandWave(band, wave)| IndividualGraphArgs {
+            name: args.name.clone(),
+            wave,
+            band,
+            freq_settings: args.freq_settings.clone(),
+            global: args.global.clone(),
+        })
+        .collect();
+    run_multiple_graph(graphs, output);
 }
 
 fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
@@ -1,56 +1,374 @@
-use std::sync::mpsc::SyncSender;
+use std::{sync::mpsc::Sender, time::Duration};
 
-use gdal::raster::StatisticsMinMax;
+use gdal::{raster::StatisticsMinMax, vector::LayerAccess};
 use itertools::Itertools;
+use proj::Transform;
+use serde::{Deserialize, Serialize};
 use tauri::State;
 
-use crate::{audio::AudioMessage, gdal_if::read_raster_data, state::AppState};
+use crate::{
+    audio::{
+        graph::RasterGraph,
+        histogram::{bin_values, AudioHistogram},
+        low_level::Playable,
+        soundscape::{Earcon, Soundscape, SoundscapeItem},
+        trace::{trace_paths, GeometryTrace, TraceSettings},
+        AudioMessage,
+    },
+    gdal_if::{get_fields, read_raster_data, FieldValue, LocalFeatureInfo},
+    geometry::{GeoGeometry, Point},
+    state::{gis::raster::StatefulRasterBand, AppState},
+};
 
+/// The most features a soundscape will play, the closest are kept
+const MAX_SOUNDSCAPE_FEATURES: usize = 40;
+
+/// Builds the graph of a raster band from its audio settings
+fn band_graph(band: &mut StatefulRasterBand) -> Result<RasterGraph, String> {
+    let StatisticsMinMax { min, max } = band
+        .band
+        .band
+        .compute_raster_min_max(false)
+        .map_err(|_| "Failed to get min max for raster".to_string())?;
+    let data = read_raster_data(&band.band.band);
+    Ok(RasterGraph::new(
+        vec![(
+            data,
+            min,
+            max,
+            band.band.no_data_value(),
+            band.info.audio_settings.graph().clone(),
+        )],
+        band.info.audio_settings.indicators(),
+    ))
+}
+
+/// Builds the histogram of a raster band from its audio settings
+fn band_histogram(band: &mut StatefulRasterBand) -> Result<AudioHistogram, String> {
+    let StatisticsMinMax { min, max } = band
+        .band
+        .band
+        .compute_raster_min_max(false)
+        .map_err(|_| "Failed to get min max for raster".to_string())?;
+    let histogram = band
+        .band
+        .band
+        .histogram(min, max, 256, true, false)
+        .map_err(|_| "Failed to get histogram".to_string())?;
+    Ok(AudioHistogram::new(
+        histogram.counts().iter().map(|x| (*x) as f64).collect_vec(),
+        band.info.audio_settings.histogram().clone(),
+        band.info.audio_settings.wave(),
+        band.info.audio_settings.indicators(),
+    ))
+}
+
+/// Plays the current raster band as a graph
+/// Returns a description of each sound in the legend played before the graph
 #[tauri::command]
 #[specta::specta]
-pub fn play_as_sound(state: AppState, audio: State<SyncSender<AudioMessage>>) {
+pub fn play_as_sound(state: AppState, audio: State<Sender<AudioMessage>>) -> Vec<String> {
     state
-        .with_current_raster_band(|band| {
-            let Ok(StatisticsMinMax { min, max }) = band.band.band.compute_raster_min_max(false)
-            else {
-                eprint!("Failed to ge min max for raster");
-                return;
-            };
-            let data = read_raster_data(&band.band.band);
-            audio
-                .send(AudioMessage::PlayRaster(
-                    data,
-                    min,
-                    max,
-                    band.band.no_data_value(),
-                    band.info.audio_settings.graph().clone(),
-                ))
-                .unwrap();
+        .with_current_raster_band(|band| match band_graph(band) {
+            Ok(graph) => {
+                let legend = graph.legend_text();
+                audio.send(AudioMessage::PlayRaster(graph)).unwrap();
+                legend
+            }
+            Err(err) => {
+                eprint!("{err}");
+                Vec::new()
+            }
         })
-        .expect("Not a raster band");
+        .expect("Not a raster band")
 }
 
+/// Plays the histogram of the current raster band
+/// Returns a description of each sound in the legend played before the histogram
 #[tauri::command]
 #[specta::specta]
-pub fn play_histogram(state: AppState, audio: State<SyncSender<AudioMessage>>) {
+pub fn play_histogram(state: AppState, audio: State<Sender<AudioMessage>>) -> Vec<String> {
     state
-        .with_current_raster_band(|band| {
-            let Ok(StatisticsMinMax { min, max }) = band.band.band.compute_raster_min_max(false)
+        .with_current_raster_band(|band| match band_histogram(band) {
+            Ok(histogram) => {
+                let legend = histogram.legend_text();
+                audio.send(AudioMessage::PlayHistogram(histogram)).unwrap();
+                legend
+            }
+            Err(err) => {
+                eprint!("{err}");
+                Vec::new()
+            }
+        })
+        .expect("Not a raster band")
+}
+
+/// Writes the graph of the current raster band to a Standard MIDI File
+#[tauri::command]
+#[specta::specta]
+pub fn export_graph_midi(path: String, state: AppState) -> Result<(), String> {
+    state
+        .with_current_raster_band(band_graph)
+        .ok_or_else(|| "Not a raster band".to_string())??
+        .save_midi(path)
+        .map_err(|err| err.to_string())
+}
+
+/// Writes the histogram of the current raster band to a Standard MIDI File
+#[tauri::command]
+#[specta::specta]
+pub fn export_histogram_midi(path: String, state: AppState) -> Result<(), String> {
+    state
+        .with_current_raster_band(band_histogram)
+        .ok_or_else(|| "Not a raster band".to_string())??
+        .save_midi(path)
+        .map_err(|err| err.to_string())
+}
+
+/// How the values of a field of a vector layer are played
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, specta::Type)]
+pub enum FieldPlayback {
+    /// The number of values that fall in each of the given number of bins
+    Histogram(usize),
+    /// Each value in the order the features are stored in the layer
+    FeatureOrder,
+    /// Each value from smallest to largest
+    Sorted,
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn play_field(
+    field: String,
+    playback: FieldPlayback,
+    state: AppState,
+    audio: State<Sender<AudioMessage>>,
+) -> Result<Vec<String>, String> {
+    let (mut values, audio_settings) = state.with_lock(|state| {
+        let audio_settings = state.settings().get_default_audio().clone();
+        state
+            .with_current_vector_layer(|mut layer| {
+                layer
+                    .layer
+                    .layer()
+                    .features()
+                    .filter_map(|feature| feature.field(&field).ok().flatten())
+                    .filter_map(|value| FieldValue::from(value).as_f64())
+                    .filter(|value| value.is_finite())
+                    .collect_vec()
+            })
+            .map(|values| (values, audio_settings))
+            .ok_or_else(|| "No vector layer selected".to_string())
+    })?;
+    if values.is_empty() {
+        return Err(format!("The field {field} has no numeric values"));
+    }
+    let y = match playback {
+        FieldPlayback::Histogram(bins) => bin_values(&values, bins),
+        FieldPlayback::FeatureOrder => values,
+        FieldPlayback::Sorted => {
+            values.sort_by(f64::total_cmp);
+            values
+        }
+    };
+    let histogram = AudioHistogram::new(
+        y,
+        audio_settings.histogram().clone(),
+        audio_settings.wave(),
+        audio_settings.indicators(),
+    );
+    let legend = histogram.legend_text();
+    audio
+        .send(AudioMessage::PlayHistogram(histogram))
+        .map_err(|_| "Failed to start playback".to_string())?;
+    Ok(legend)
+}
+
+/// Plays the outline of the selected feature over the given number of milliseconds
+#[tauri::command]
+#[specta::specta]
+pub fn play_feature_geometry(
+    duration: u64,
+    state: AppState,
+    audio: State<Sender<AudioMessage>>,
+) -> Result<(), String> {
+    let (feature, audio_settings) = state.with_lock(|state| {
+        let audio_settings = state.settings().get_default_audio().clone();
+        state
+            .with_current_dataset_mut(|dataset, _| dataset.get_current_feature())
+            .flatten()
+            .map(|feature| (feature, audio_settings))
+            .ok_or_else(|| "No feature selected".to_string())
+    })?;
+    let geometry = feature
+        .geometry
+        .ok_or("The selected feature has no geometry")?;
+    let paths = trace_paths(&GeoGeometry::from(geometry));
+    if paths.is_empty() {
+        return Err("Points have no outline to play".to_string());
+    }
+    let graph = audio_settings.graph();
+    let settings = TraceSettings {
+        duration: Duration::from_millis(duration),
+        min_freq: audio_settings.min_freq,
+        max_freq: audio_settings.max_freq,
+        wave: graph.wave,
+        mapping: graph.mapping,
+    };
+    audio
+        .send(AudioMessage::PlayTrace(GeometryTrace::new(
+            paths,
+            settings,
+            audio_settings.indicators(),
+        )))
+        .map_err(|_| "Failed to start playback".to_string())
+}
+
+/// A feature played as part of a soundscape, in the order they are played
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, specta::Type)]
+pub struct NearbyFeature {
+    pub name: Option<String>,
+    /// The value of the field used to pick the features sound if one was given
+    pub class: Option<String>,
+    /// Metres from the point
+    pub distance: f64,
+    /// Degrees clockwise from north
+    pub bearing: f64,
+}
+
+/// Plays a short sound for each feature of every open vector layer within radius metres of a point
+/// Features are played clockwise starting from north, panned by their bearing and quieter the further away they are
+/// Each feature sounds like its geometry type unless a field is given to classify them by
+#[tauri::command]
+#[specta::specta]
+pub fn play_soundscape(
+    point: Point,
+    radius: f64,
+    class_field: Option<String>,
+    state: AppState,
+    audio: State<Sender<AudioMessage>>,
+) -> Result<Vec<NearbyFeature>, String> {
+    if radius <= 0.0 {
+        return Err("The radius must be greater than 0".to_string());
+    }
+    let mut guard = state.data.lock().unwrap();
+    // Like the other exploration commands the point is relative to the current raster if there is one
+    let centre = guard
+        .with_current_raster_band(|band| band.band.point_to_wgs84(point.into()))
+        .flatten()
+        .unwrap_or_else(|| point.into());
+    let audio_settings = guard.settings().get_default_audio().clone();
+    let mut classes = Vec::<String>::new();
+    let mut nearby = Vec::new();
+    for mut layer in guard.shared.get_all_vectors() {
+        let srs = layer
+            .layer
+            .layer()
+            .spatial_ref()
+            .and_then(|srs| srs.to_wkt().ok());
+        let name_field = layer.info.primary_field_name.clone();
+        for feature in layer.layer.layer().features() {
+            let Some(geometry) = feature
+                .geometry()
+                .and_then(|geometry| geometry.to_geo().ok())
             else {
-                eprint!("Failed to ge min max for raster");
-                return;
+                continue;
+            };
+            let geometry = match &srs {
+                Some(srs) => match geometry.transformed_crs_to_crs(srs, "WGS84") {
+                    Ok(geometry) => geometry,
+                    Err(_) => continue,
+                },
+                None => geometry,
+            };
+            let feature = LocalFeatureInfo {
+                fields: get_fields(&feature),
+                geometry,
+            };
+            let Some((distance, bearing)) = feature.distance_and_bearing(&centre) else {
+                continue;
             };
-            let Ok(histogram) = band.band.band.histogram(min, max, 256, true, false) else {
-                eprint!("Failed to get histogram");
-                return;
+            if distance > radius {
+                continue;
+            }
+            let class = class_field
+                .as_ref()
+                .and_then(|field| feature.get_field(field));
+            let earcon = match &class {
+                Some(class) => Earcon::Class(match classes.iter().position(|x| x == class) {
+                    Some(index) => index,
+                    None => {
+                        classes.push(class.clone());
+                        classes.len() - 1
+                    }
+                }),
+                None => Earcon::for_geometry(&feature.geometry),
             };
-            audio
-                .send(AudioMessage::PlayHistogram(
-                    histogram.counts().iter().map(|x| (*x) as f64).collect_vec(),
-                    band.info.audio_settings.histogram().clone(),
-                    Default::default(),
-                ))
-                .unwrap();
+            let name = match &name_field {
+                Some(field) => feature.get_field(field),
+                None => feature.get_name(),
+            };
+            nearby.push((
+                NearbyFeature {
+                    name,
+                    class,
+                    distance: distance.round(),
+                    bearing: bearing.round(),
+                },
+                earcon,
+            ));
+        }
+    }
+    drop(guard);
+    nearby.sort_by(|a, b| a.0.distance.total_cmp(&b.0.distance));
+    nearby.truncate(MAX_SOUNDSCAPE_FEATURES);
+    nearby.sort_by(|a, b| a.0.bearing.total_cmp(&b.0.bearing));
+    let items = nearby
+        .iter()
+        .map(|(feature, earcon)| SoundscapeItem {
+            earcon: *earcon,
+            bearing: feature.bearing,
+            distance: feature.distance,
         })
-        .expect("Not a raster band");
+        .collect_vec();
+    audio
+        .send(AudioMessage::PlaySoundscape(Soundscape::new(
+            items,
+            radius,
+            audio_settings.min_freq,
+            audio_settings.max_freq,
+            audio_settings.indicators(),
+        )))
+        .map_err(|_| "Failed to start playback".to_string())?;
+    Ok(nearby.into_iter().map(|(feature, _)| feature).collect())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn stop_audio(audio: State<Sender<AudioMessage>>) {
+    audio.send(AudioMessage::Stop).unwrap();
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn pause_audio(audio: State<Sender<AudioMessage>>) {
+    audio.send(AudioMessage::Pause).unwrap();
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn resume_audio(audio: State<Sender<AudioMessage>>) {
+    audio.send(AudioMessage::Resume).unwrap();
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn skip_legend(audio: State<Sender<AudioMessage>>) {
+    audio.send(AudioMessage::SkipLegend).unwrap();
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn skip_to_row(row: usize, audio: State<Sender<AudioMessage>>) {
+    audio.send(AudioMessage::SkipToRow(row)).unwrap();
 }
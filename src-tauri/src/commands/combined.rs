@@ -1,8 +1,16 @@
 use gdal::spatial_ref::SpatialRef;
+use itertools::Itertools;
 
 use crate::{
-    gdal_if::Srs,
-    state::{gis::combined::StatefulLayerEnum, AppState},
+    dataset_collection::NonEmptyDelegatorImpl,
+    gdal_if::{
+        zonal::{write_zonal_csv, write_zonal_layer, zonal_statistics, ZonalOutput},
+        Srs,
+    },
+    state::{
+        gis::combined::{RasterIndex, StatefulLayerEnum, VectorIndex},
+        AppState,
+    },
 };
 
 #[tauri::command]
@@ -36,3 +44,35 @@ pub fn set_srs(srs: Srs, state: AppState) {
     .unwrap();
     state.with_current_dataset_mut(|ds, _| ds.dataset.dataset.set_spatial_ref(&srs).unwrap());
 }
+
+/// Works out the count, min, max, mean, standard deviation, majority and class percentages of a raster band within each polygon of a layer
+/// The polygons are reprojected to match the raster if needed
+#[tauri::command]
+#[specta::specta]
+pub fn calc_zonal_statistics(
+    zones: VectorIndex,
+    raster: RasterIndex,
+    output: ZonalOutput,
+    state: AppState,
+) -> Result<(), String> {
+    state.with_lock(|state| {
+        let datasets = state.shared.datasets.iter().collect_vec();
+        let zones_dataset = &datasets
+            .get(zones.dataset)
+            .ok_or_else(|| "The polygon layer isn't open".to_string())?
+            .dataset;
+        let raster_dataset = &datasets
+            .get(raster.dataset)
+            .ok_or_else(|| "The raster isn't open".to_string())?
+            .dataset;
+        let table = zonal_statistics(zones_dataset, zones.layer, raster_dataset, raster.band)?;
+        match output {
+            ZonalOutput::Csv(path) => write_zonal_csv(zones_dataset, zones.layer, &table, &path),
+            ZonalOutput::Layer(path) => {
+                let dataset = write_zonal_layer(zones_dataset, zones.layer, &table, &path)?;
+                state.add_dataset(dataset);
+                Ok(())
+            }
+        }
+    })
+}
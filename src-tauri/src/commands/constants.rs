@@ -2,7 +2,7 @@ use itertools::Itertools;
 use strum::IntoEnumIterator;
 
 use crate::{
-    audio::Waveform,
+    audio::{graph::ScanOrder, mapping::PitchMapping, Waveform},
     state::{gis::raster::RenderMethod, settings::AudioIndicator},
 };
 
@@ -25,3 +25,15 @@ pub fn get_audio_indicators() -> Vec<AudioIndicator> {
 pub fn get_wave_forms() -> Vec<Waveform> {
     Waveform::iter().collect_vec()
 }
+
+#[tauri::command]
+#[specta::specta]
+pub fn get_pitch_mappings() -> Vec<PitchMapping> {
+    PitchMapping::iter().collect_vec()
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn get_scan_orders() -> Vec<ScanOrder> {
+    ScanOrder::iter().collect_vec()
+}
@@ -1,5 +1,17 @@
+use gdal::raster::{processing::dem::HillshadeOptions, GdalDataType};
+
 use crate::{
-    gdal_if::processing::{aspect, roughness, slope},
+    gdal_if::{
+        contour::{contours, ContourSettings},
+        create_raster_like,
+        processing::{
+            aspect, curvature, geographic_scale, hillshade, roughness, slope,
+            terrain_ruggedness_index, topographic_position_index, AspectSettings, CurvatureKind,
+            RoughnessSettings, SlopeSettings,
+        },
+        viewshed::{viewshed, ObserverPosition, VIEWSHED_NO_DATA},
+        WrappedDataset,
+    },
     state::AppState,
 };
 
@@ -21,8 +33,134 @@ macro_rules! gen_processing_command {
             })
         }
     };
+    ($command_name:ident, $processing_name:ident, $settings:ty) => {
+        #[tauri::command]
+        #[specta::specta]
+        pub fn $command_name(name: String, settings: $settings, state: AppState) {
+            state.with_lock(|state| {
+                let res = state
+                    .create_from_current_dataset(|ds| {
+                        let options = settings.to_options(&ds.dataset.dataset)?;
+                        $processing_name(&ds.dataset, name, &options).map_err(|e| e.to_string())
+                    })
+                    .expect(
+                        "Attempted to operate on current dataset but there is no current dataset selected",
+                    );
+                if let Err(e) = res {
+                    state.errors.push(e)
+                }
+            })
+        }
+    };
 }
 
-gen_processing_command!(calc_slope, slope);
-gen_processing_command!(calc_aspect, aspect);
-gen_processing_command!(calc_roughness, roughness);
+gen_processing_command!(calc_slope, slope, SlopeSettings);
+gen_processing_command!(calc_aspect, aspect, AspectSettings);
+gen_processing_command!(calc_roughness, roughness, RoughnessSettings);
+gen_processing_command!(calc_tpi, topographic_position_index);
+gen_processing_command!(calc_tri, terrain_ruggedness_index);
+
+/// Generates a hillshade lit from the given azimuth and altitude in degrees
+/// DEMs in latitude and longitude are scaled so their slopes are realistic
+#[tauri::command]
+#[specta::specta]
+pub fn calc_hillshade(name: String, azimuth: f64, altitude: f64, state: AppState) {
+    state.with_lock(|state| {
+        let res = state
+            .create_from_current_dataset(|ds| {
+                let mut options = HillshadeOptions::new();
+                options.with_azimuth(azimuth).with_altitude(altitude);
+                if let Some(scale) = geographic_scale(&ds.dataset.dataset) {
+                    options.with_scale(scale);
+                }
+                hillshade(&ds.dataset, name, &options)
+            })
+            .expect(
+                "Attempted to operate on current dataset but there is no current dataset selected",
+            );
+        if let Err(e) = res {
+            state.errors.push(e.to_string())
+        }
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn calc_curvature(name: String, kind: CurvatureKind, state: AppState) {
+    state.with_lock(|state| {
+        let res = state
+            .create_from_current_dataset(|ds| curvature(&ds.dataset, name, kind))
+            .expect(
+                "Attempted to operate on current dataset but there is no current dataset selected",
+            );
+        if let Err(e) = res {
+            state.errors.push(e)
+        }
+    })
+}
+
+/// Works out what can be seen from an observer on the current band and adds it as a new dataset
+/// Returns a summary of the visible area and the directions that can be seen furthest
+#[tauri::command]
+#[specta::specta]
+pub fn calc_viewshed(
+    observer: ObserverPosition,
+    observer_height: f64,
+    target_height: f64,
+    max_distance: Option<f64>,
+    name: String,
+    state: AppState,
+) -> Result<String, String> {
+    state.with_lock(|state| {
+        let mut summary = String::new();
+        state
+            .create_from_current_dataset(|ds| {
+                let viewshed = {
+                    let band = ds
+                        .get_current_layer()
+                        .and_then(|layer| layer.try_into_raster().ok())
+                        .ok_or_else(|| "The current layer isn't a raster".to_string())?;
+                    viewshed(
+                        &band.band,
+                        observer,
+                        observer_height,
+                        target_height,
+                        max_distance,
+                    )?
+                };
+                summary = viewshed.summary;
+                let output = create_raster_like(
+                    &ds.dataset.dataset,
+                    &name,
+                    &viewshed.visibility,
+                    GdalDataType::UInt8,
+                    Some(VIEWSHED_NO_DATA),
+                )?;
+                Ok::<_, String>(WrappedDataset::wrap_existing(output, name))
+            })
+            .ok_or_else(|| "There is no current dataset".to_string())??;
+        Ok(summary)
+    })
+}
+
+/// Traces contour lines over the current band and adds them as a new vector dataset
+#[tauri::command]
+#[specta::specta]
+pub fn generate_contours(
+    settings: ContourSettings,
+    path: String,
+    state: AppState,
+) -> Result<(), String> {
+    state.with_lock(|state| {
+        state
+            .create_from_current_dataset(|ds| {
+                let band = ds
+                    .get_current_layer()
+                    .and_then(|layer| layer.try_into_raster().ok())
+                    .ok_or_else(|| "The current layer isn't a raster".to_string())?;
+                contours(&band.band, &settings, &path)
+            })
+            .ok_or_else(|| "There is no current dataset".to_string())??;
+        Ok(())
+    })
+}
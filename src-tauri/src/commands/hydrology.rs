@@ -0,0 +1,94 @@
+use itertools::Itertools;
+
+use crate::{
+    dataset_collection::NonEmptyDelegatorImpl,
+    gdal_if::{
+        hydrology::{
+            write_accumulation, write_directions, write_filled, write_streams, write_watershed,
+            FlowModel,
+        },
+        WrappedDataset,
+    },
+    geometry::Point,
+    state::{gis::combined::RasterIndex, AppState},
+};
+
+/// Routes flow over a DEM from the open datasets and adds whatever is written from it to them
+fn with_flow_model<F>(dem: RasterIndex, state: AppState, write: F) -> Result<(), String>
+where
+    F: FnOnce(&WrappedDataset, FlowModel) -> Result<WrappedDataset, String>,
+{
+    state.with_lock(|state| {
+        let datasets = state.shared.datasets.iter().collect_vec();
+        let dataset = &datasets
+            .get(dem.dataset)
+            .ok_or_else(|| "The DEM isn't open".to_string())?
+            .dataset;
+        let model = FlowModel::new(dataset, dem.band)?;
+        let output = write(dataset, model)?;
+        state.add_dataset(output);
+        Ok(())
+    })
+}
+
+/// Fills the depressions of a DEM so water can flow off it from every cell
+#[tauri::command]
+#[specta::specta]
+pub fn fill_dem_depressions(dem: RasterIndex, name: String, state: AppState) -> Result<(), String> {
+    with_flow_model(dem, state, |dataset, model| {
+        write_filled(dataset, &model, name)
+    })
+}
+
+/// Generates the D8 flow direction of each cell of a DEM after filling its depressions
+#[tauri::command]
+#[specta::specta]
+pub fn calc_flow_direction(dem: RasterIndex, name: String, state: AppState) -> Result<(), String> {
+    with_flow_model(dem, state, |dataset, model| {
+        write_directions(dataset, &model, name)
+    })
+}
+
+/// Generates how many cells drain through each cell of a DEM after filling its depressions
+#[tauri::command]
+#[specta::specta]
+pub fn calc_flow_accumulation(
+    dem: RasterIndex,
+    name: String,
+    state: AppState,
+) -> Result<(), String> {
+    with_flow_model(dem, state, |dataset, model| {
+        write_accumulation(dataset, &model, name)
+    })
+}
+
+/// Extracts the streams of a DEM as lines where at least threshold cells drain through them
+#[tauri::command]
+#[specta::specta]
+pub fn extract_streams(
+    dem: RasterIndex,
+    threshold: f64,
+    path: String,
+    state: AppState,
+) -> Result<(), String> {
+    if threshold < 1.0 {
+        return Err("The threshold must be at least 1 cell".to_string());
+    }
+    with_flow_model(dem, state, |dataset, model| {
+        write_streams(dataset, model.stream_links(threshold), &path)
+    })
+}
+
+/// Outlines the area draining through a pour point given in the projection of the DEM
+#[tauri::command]
+#[specta::specta]
+pub fn delineate_watershed(
+    dem: RasterIndex,
+    pour_point: Point,
+    path: String,
+    state: AppState,
+) -> Result<(), String> {
+    with_flow_model(dem, state, |dataset, model| {
+        write_watershed(dataset, model.watershed(pour_point.x, pour_point.y)?, &path)
+    })
+}
@@ -4,6 +4,7 @@ mod constants;
 mod context;
 mod dataset;
 mod dem;
+mod hydrology;
 mod individual_settings;
 mod raster;
 mod settings;
@@ -24,6 +25,7 @@ pub use constants::*;
 pub use context::*;
 pub use dataset::*;
 pub use dem::*;
+pub use hydrology::*;
 pub use individual_settings::*;
 pub use raster::*;
 pub use settings::*;
@@ -65,21 +67,47 @@ pub fn generate_handlers(
             get_image_pixels,
             set_name_field,
             classify_current_raster,
+            calculate_raster,
             set_srs,
+            calc_zonal_statistics,
             reproject_layer,
             copy_features,
             simplify_layer,
             calc_slope,
             calc_aspect,
             calc_roughness,
+            calc_hillshade,
+            calc_tpi,
+            calc_tri,
+            calc_curvature,
+            calc_viewshed,
+            generate_contours,
+            fill_dem_depressions,
+            calc_flow_direction,
+            calc_flow_accumulation,
+            extract_streams,
+            delineate_watershed,
             play_as_sound,
             play_histogram,
+            export_graph_midi,
+            export_histogram_midi,
+            play_field,
+            play_feature_geometry,
+            play_soundscape,
+            stop_audio,
+            pause_audio,
+            resume_audio,
+            skip_legend,
+            skip_to_row,
             generate_counts_report,
+            calc_band_statistics,
             open_settings,
             set_settings,
             get_render_methods,
             get_audio_indicators,
             get_wave_forms,
+            get_pitch_mappings,
+            get_scan_orders,
             set_display_raster,
             set_display_vector,
             set_current_ocr,
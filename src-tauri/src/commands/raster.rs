@@ -1,4 +1,4 @@
-use std::{cmp::Ordering, process::Command};
+use std::cmp::Ordering;
 
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
@@ -6,9 +6,13 @@ use tauri::State;
 
 use crate::{
     dataset_collection::NonEmptyDelegatorImpl,
-    gdal_if::{read_raster_data, read_raster_data_enum_as},
+    gdal_if::{
+        processing::{calculate, classify, Classification},
+        read_raster_data, read_raster_data_enum_as,
+    },
     geometry::Point,
     state::{settings::AudioSettings, AppState},
+    stats::BandStatistics,
     web_socket::{AppMessage, GisMessage, RasterMessage, TouchDevice, VectorMessage},
 };
 
@@ -47,49 +51,74 @@ pub fn generate_counts_report(name: String, state: AppState) {
     })
 }
 
+/// Works out the statistics of the current band, also writing them to a CSV file when a path is given
+/// Percentiles are given between 0 and 100
+#[tauri::command]
+#[specta::specta]
+pub fn calc_band_statistics(
+    percentiles: Vec<f64>,
+    csv: Option<String>,
+    state: AppState,
+) -> Result<BandStatistics, String> {
+    let statistics = state
+        .with_current_raster_band(|band| band.band.statistics(&percentiles))
+        .ok_or_else(|| "No raster band selected".to_string())??;
+    if let Some(path) = csv {
+        statistics.write_csv(path)?;
+    }
+    Ok(statistics)
+}
+
+/// Classifies the current raster band into a new dataset
+/// Each value takes the target of the first classification that contains it
+/// Values that match no classification take the default, or become no data if there isn't one
 #[tauri::command]
 #[specta::specta]
 pub fn classify_current_raster(
     dest: String,
     classifications: Vec<Classification>,
+    default: Option<f64>,
     state: AppState,
-) {
-    let classifications = classifications
-        .into_iter()
-        .map(Classification::into_calc_string)
-        .join("+");
-    state.with_current_dataset_mut(|dataset, _| {
-        let mut cmd = Command::new("gdal_calc.py");
-        cmd.arg("-A")
-            .arg(&dataset.dataset.file_name)
-            .arg(format!("--outfile={}", dest))
-            .arg(format!("--calc=\"{}\"", classifications))
-            .arg(format!(
-                "--NoDataValue={}",
-                dataset
-                    .dataset
-                    .dataset
-                    .rasterband(1)
-                    .unwrap()
-                    .no_data_value()
-                    .unwrap()
-            ));
-        let output = cmd.output().expect("Failed to classify raster");
-        eprint!("{:?}", output);
-    });
-}
-
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
-pub struct Classification {
-    pub min: f64,
-    pub max: f64,
-    pub target: f64,
-}
-
-impl Classification {
-    fn into_calc_string(self) -> String {
-        format!("{}*(A>{})*(A<={})", self.target, self.min, self.max)
-    }
+) -> Result<(), String> {
+    state.with_lock(|state| {
+        state
+            .create_from_current_dataset(|dataset| {
+                let band_index = *dataset
+                    .layer_index
+                    .ok_or_else(|| "No raster band selected".to_string())?
+                    .as_raster()
+                    .ok_or_else(|| "No raster band selected".to_string())?;
+                classify(
+                    &dataset.dataset,
+                    band_index,
+                    dest,
+                    &classifications,
+                    default,
+                )
+            })
+            .ok_or_else(|| "No dataset selected".to_string())?
+            .map(|_| ())
+    })
+}
+
+/// Evaluates a map algebra expression like (B2 - B1) / (B2 + B1) and adds the result as a new dataset
+/// B followed by a number is a band of the current dataset, any other name is the first band of the open dataset with that file name
+/// A band of another dataset can be picked with its name followed by the band, like dem.B2
+#[tauri::command]
+#[specta::specta]
+pub fn calculate_raster(expression: String, dest: String, state: AppState) -> Result<(), String> {
+    state.with_lock(|state| {
+        let current = state.with_current_dataset_mut(|_, index| index);
+        let datasets = state
+            .shared
+            .datasets
+            .iter()
+            .map(|dataset| &dataset.dataset)
+            .collect_vec();
+        let output = calculate(&datasets, current, &expression, dest)?;
+        state.add_dataset(output);
+        Ok(())
+    })
 }
 
 #[tauri::command]
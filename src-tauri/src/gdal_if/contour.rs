@@ -0,0 +1,110 @@
+use std::ptr;
+
+use gdal::{
+    cpl::CslStringList,
+    spatial_ref::SpatialRef,
+    vector::{LayerAccess, OGRwkbGeometryType},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{create_vector_dataset, FieldType, WrappedDataset, WrappedRasterBand};
+
+fn default_elevation_field() -> String {
+    "elevation".to_string()
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+pub struct ContourSettings {
+    /// The difference in elevation between each line
+    pub interval: f64,
+    /// The elevation the intervals are counted from
+    #[serde(default)]
+    pub base: f64,
+    /// Specific elevations to draw lines at, used instead of the interval when given
+    #[serde(default)]
+    pub fixed_levels: Vec<f64>,
+    /// The name of the field each line's elevation is written to
+    #[serde(default = "default_elevation_field")]
+    pub elevation_field: String,
+}
+
+impl ContourSettings {
+    fn to_options(&self, no_data: Option<f64>) -> Result<CslStringList, String> {
+        let mut options = CslStringList::new();
+        let mut set = |name: &str, value: String| {
+            options
+                .set_name_value(name, &value)
+                .map_err(|_| format!("Invalid contour option {name}={value}"))
+        };
+        if self.fixed_levels.is_empty() {
+            if !(self.interval.is_finite() && self.interval > 0.0) {
+                return Err("The contour interval must be more than 0".to_string());
+            }
+            if !self.base.is_finite() {
+                return Err("The contour base must be a number".to_string());
+            }
+            set("LEVEL_INTERVAL", self.interval.to_string())?;
+            set("LEVEL_BASE", self.base.to_string())?;
+        } else {
+            if self.fixed_levels.iter().any(|level| !level.is_finite()) {
+                return Err("Every fixed contour level must be a number".to_string());
+            }
+            let levels = self
+                .fixed_levels
+                .iter()
+                .map(f64::to_string)
+                .collect::<Vec<_>>();
+            set("FIXED_LEVELS", levels.join(","))?;
+        }
+        if let Some(no_data) = no_data {
+            set("NODATA", no_data.to_string())?;
+        }
+        // The fields are given by their index in the layer
+        set("ID_FIELD", "0".to_string())?;
+        set("ELEV_FIELD", "1".to_string())?;
+        Ok(options)
+    }
+}
+
+/// Traces contour lines over a band into a new vector dataset
+/// Each line gets an id and its elevation
+pub fn contours(
+    band: &WrappedRasterBand,
+    settings: &ContourSettings,
+    path: &str,
+) -> Result<WrappedDataset, String> {
+    if settings.elevation_field.is_empty() || settings.elevation_field == "id" {
+        return Err("The elevation field needs a name other than id".to_string());
+    }
+    let options = settings.to_options(band.no_data_value())?;
+    let srs = band
+        .srs
+        .as_deref()
+        .and_then(|wkt| SpatialRef::from_wkt(wkt).ok());
+    create_vector_dataset(
+        path,
+        srs.as_ref(),
+        OGRwkbGeometryType::wkbLineString,
+        |layer| {
+            layer
+                .create_defn_fields(&[
+                    ("id", FieldType::OFTInteger as u32),
+                    (settings.elevation_field.as_str(), FieldType::OFTReal as u32),
+                ])
+                .map_err(|_| "Failed to add the contour fields".to_string())?;
+            let result = unsafe {
+                gdal_sys::GDALContourGenerateEx(
+                    band.band.c_rasterband(),
+                    layer.c_layer() as *mut _,
+                    options.as_ptr(),
+                    None,
+                    ptr::null_mut(),
+                )
+            };
+            if result != gdal_sys::CPLErr::CE_None {
+                return Err("Failed to generate the contours".to_string());
+            }
+            Ok(())
+        },
+    )
+}
@@ -66,6 +66,18 @@ impl ToString for FieldValue {
     }
 }
 
+impl FieldValue {
+    /// The value as a number if it is a single integer or real
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FieldValue::Integer(val) => Some(*val as f64),
+            FieldValue::Integer64(val) => Some(*val as f64),
+            FieldValue::Real(val) => Some(*val),
+            _ => None,
+        }
+    }
+}
+
 impl From<GdalFieldValue> for FieldValue {
     fn from(value: GdalFieldValue) -> Self {
         match value {
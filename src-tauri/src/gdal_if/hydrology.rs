@@ -0,0 +1,614 @@
+//! Routes water over a DEM with the D8 method, where each cell drains into whichever of its 8 neighbours it is steepest towards
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use gdal::{
+    raster::GdalDataType,
+    vector::{FieldValue as GdalFieldValue, LayerAccess, OGRwkbGeometryType, ToGdal},
+    GeoTransform, GeoTransformEx,
+};
+use geo_types::{Coord, LineString, MultiPolygon, Rect};
+use geos::Geom;
+use itertools::Itertools;
+use ndarray::Array2;
+
+use super::{
+    create_raster_like, create_vector_dataset,
+    processing::{dataset_scale, DEM_NO_DATA},
+    read_raster_data, FieldType, WrappedDataset,
+};
+
+/// The offset of each neighbour as (row, column) along with its direction code
+/// The codes are the powers of 2 used by ArcGIS and most other GIS software, starting east and going clockwise
+const NEIGHBOURS: [(isize, isize, u8); 8] = [
+    (0, 1, 1),
+    (1, 1, 2),
+    (1, 0, 4),
+    (1, -1, 8),
+    (0, -1, 16),
+    (-1, -1, 32),
+    (-1, 0, 64),
+    (-1, 1, 128),
+];
+
+/// The direction of cells that drain out of the DEM
+const OUTLET: u8 = 0;
+const DIRECTION_NO_DATA: u8 = 255;
+
+/// How much higher a filled cell is made than the one it drains into so flats still have a direction to flow
+const FLAT_INCREMENT: f64 = 1e-5;
+
+/// How many cells away a pour point can be moved to land on the cell with the most flow
+const SNAP_RADIUS: usize = 2;
+
+type Cell = (usize, usize);
+
+fn neighbour(
+    (row, col): Cell,
+    (row_offset, col_offset): (isize, isize),
+    dim: Cell,
+) -> Option<Cell> {
+    let row = row.checked_add_signed(row_offset)?;
+    let col = col.checked_add_signed(col_offset)?;
+    (row < dim.0 && col < dim.1).then_some((row, col))
+}
+
+fn downstream(cell: Cell, direction: u8, dim: Cell) -> Option<Cell> {
+    NEIGHBOURS
+        .iter()
+        .find(|(_, _, code)| *code == direction)
+        .and_then(|(row, col, _)| neighbour(cell, (*row, *col), dim))
+}
+
+/// A cell waiting to be filled, ordered so the lowest cell comes out of the heap first
+struct QueuedCell {
+    elevation: f64,
+    cell: Cell,
+}
+
+impl PartialEq for QueuedCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedCell {}
+
+impl PartialOrd for QueuedCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.elevation.total_cmp(&self.elevation)
+    }
+}
+
+/// Fills every depression so each cell has a downhill path to the edge of the DEM or to no data
+/// Uses the priority flood algorithm, raising filled cells slightly above their outlet so flats drain too
+/// No data is represented by NaN
+pub fn fill_depressions(dem: &Array2<f64>) -> Array2<f64> {
+    let dim = dem.dim();
+    let mut filled = dem.clone();
+    let mut closed = dem.mapv(f64::is_nan);
+    let mut open = BinaryHeap::new();
+    for (cell, elevation) in dem.indexed_iter() {
+        if elevation.is_nan() {
+            continue;
+        }
+        let on_edge = NEIGHBOURS.iter().any(|(row, col, _)| {
+            neighbour(cell, (*row, *col), dim).map_or(true, |neighbour| dem[neighbour].is_nan())
+        });
+        if on_edge {
+            closed[cell] = true;
+            open.push(QueuedCell {
+                elevation: *elevation,
+                cell,
+            });
+        }
+    }
+    while let Some(QueuedCell { elevation, cell }) = open.pop() {
+        for (row, col, _) in NEIGHBOURS {
+            let Some(neighbour) = neighbour(cell, (row, col), dim) else {
+                continue;
+            };
+            if closed[neighbour] {
+                continue;
+            }
+            closed[neighbour] = true;
+            if filled[neighbour] <= elevation {
+                filled[neighbour] = elevation + FLAT_INCREMENT;
+            }
+            open.push(QueuedCell {
+                elevation: filled[neighbour],
+                cell: neighbour,
+            });
+        }
+    }
+    filled
+}
+
+/// Works out the D8 direction of each cell of a filled DEM
+/// Cells with no lower neighbour drain out of the DEM and are given a direction of 0
+pub fn flow_directions(filled: &Array2<f64>, (dx, dy): (f64, f64)) -> Array2<u8> {
+    let dim = filled.dim();
+    Array2::from_shape_fn(dim, |cell| {
+        let elevation = filled[cell];
+        if elevation.is_nan() {
+            return DIRECTION_NO_DATA;
+        }
+        NEIGHBOURS
+            .iter()
+            .filter_map(|(row, col, code)| {
+                let neighbour = neighbour(cell, (*row, *col), dim)?;
+                let distance = (*row as f64 * dy).hypot(*col as f64 * dx);
+                let gradient = (elevation - filled[neighbour]) / distance;
+                (gradient > 0.0).then_some((gradient, *code))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map_or(OUTLET, |(_, code)| code)
+    })
+}
+
+/// The cells of a filled DEM from highest to lowest, so every cell comes before the cells it drains into
+fn cells_downhill(filled: &Array2<f64>) -> Vec<Cell> {
+    filled
+        .indexed_iter()
+        .filter(|(_, elevation)| !elevation.is_nan())
+        .sorted_by(|a, b| b.1.total_cmp(a.1))
+        .map(|(cell, _)| cell)
+        .collect()
+}
+
+/// Counts the cells that drain through each cell, including itself
+/// Cells that are no data are NaN
+pub fn flow_accumulation(filled: &Array2<f64>, directions: &Array2<u8>) -> Array2<f64> {
+    let dim = filled.dim();
+    let mut accumulation = filled.mapv(|elevation| if elevation.is_nan() { f64::NAN } else { 1.0 });
+    for cell in cells_downhill(filled) {
+        if let Some(next) = downstream(cell, directions[cell], dim) {
+            accumulation[next] += accumulation[cell];
+        }
+    }
+    accumulation
+}
+
+/// The DEM with its depressions filled along with the direction and accumulation of flow over it
+pub struct FlowModel {
+    pub filled: Array2<f64>,
+    pub directions: Array2<u8>,
+    pub accumulation: Array2<f64>,
+    geo_transform: GeoTransform,
+}
+
+impl FlowModel {
+    /// Fills and routes flow over a band of a DEM
+    pub fn new(dem: &WrappedDataset, band_index: usize) -> Result<Self, String> {
+        let band = dem
+            .dataset
+            .rasterband(band_index)
+            .map_err(|_| format!("Failed to read band {band_index} of {}", dem.file_name))?;
+        let no_data = band.no_data_value();
+        let data = read_raster_data(&band).mapv(|value| {
+            if no_data == Some(value) {
+                f64::NAN
+            } else {
+                value
+            }
+        });
+        let geo_transform = dem
+            .dataset
+            .geo_transform()
+            .map_err(|_| format!("{} has no position information", dem.file_name))?;
+        // Cells in latitude and longitude are narrower than they are tall away from the equator
+        let (x_scale, y_scale) = dataset_scale(&dem.dataset).unwrap_or((1.0, 1.0));
+        let cell_size = (
+            geo_transform[1].abs() * x_scale,
+            geo_transform[5].abs() * y_scale,
+        );
+        Ok(Self::from_dem(&data, cell_size, geo_transform))
+    }
+
+    /// Fills and routes flow over elevations with no data as NaN
+    /// Only the ratio of the sides of the cells matters when picking the steepest neighbour
+    fn from_dem(dem: &Array2<f64>, cell_size: (f64, f64), geo_transform: GeoTransform) -> Self {
+        let filled = fill_depressions(dem);
+        let directions = flow_directions(&filled, cell_size);
+        let accumulation = flow_accumulation(&filled, &directions);
+        Self {
+            filled,
+            directions,
+            accumulation,
+            geo_transform,
+        }
+    }
+
+    fn dim(&self) -> Cell {
+        self.filled.dim()
+    }
+
+    fn centre(&self, (row, col): Cell) -> Coord {
+        let (x, y) = self.geo_transform.apply(col as f64 + 0.5, row as f64 + 0.5);
+        Coord { x, y }
+    }
+
+    fn corner(&self, row: usize, col: usize) -> Coord {
+        let (x, y) = self.geo_transform.apply(col as f64, row as f64);
+        Coord { x, y }
+    }
+
+    /// Finds the cells with an accumulation of at least the threshold and splits them into links between junctions
+    pub fn stream_links(&self, threshold: f64) -> Vec<StreamLink> {
+        let dim = self.dim();
+        let is_stream = |cell: Cell| self.accumulation[cell] >= threshold;
+        let next_stream = |cell: Cell| {
+            downstream(cell, self.directions[cell], dim).filter(|next| is_stream(*next))
+        };
+        let mut inflows = Array2::<usize>::zeros(dim);
+        // The highest Strahler order flowing into each cell and how many streams have it
+        let mut upstream_orders = Array2::<(u32, usize)>::from_elem(dim, (0, 0));
+        let mut orders = Array2::<u32>::zeros(dim);
+        for cell in cells_downhill(&self.filled) {
+            if !is_stream(cell) {
+                continue;
+            }
+            orders[cell] = match upstream_orders[cell] {
+                (0, _) => 1,
+                (order, count) if count > 1 => order + 1,
+                (order, _) => order,
+            };
+            if let Some(next) = next_stream(cell) {
+                inflows[next] += 1;
+                let (highest, count) = &mut upstream_orders[next];
+                match orders[cell].cmp(highest) {
+                    Ordering::Greater => (*highest, *count) = (orders[cell], 1),
+                    Ordering::Equal => *count += 1,
+                    Ordering::Less => {}
+                }
+            }
+        }
+        let mut links = Vec::new();
+        for (start, _) in self.accumulation.indexed_iter() {
+            if !is_stream(start) || inflows[start] == 1 {
+                continue;
+            }
+            let mut cells = vec![start];
+            let mut current = start;
+            while let Some(next) = next_stream(current) {
+                cells.push(next);
+                if inflows[next] > 1 {
+                    break;
+                }
+                current = next;
+            }
+            if cells.len() > 1 {
+                links.push(StreamLink {
+                    order: orders[start],
+                    upstream_cells: self.accumulation[current],
+                    line: cells.into_iter().map(|cell| self.centre(cell)).collect(),
+                });
+            }
+        }
+        links
+    }
+
+    /// Finds the cell a pour point falls in, moved to the cell with the most flow nearby in case it is slightly off the stream
+    fn pour_cell(&self, x: f64, y: f64) -> Result<Cell, String> {
+        let inverse = self
+            .geo_transform
+            .invert()
+            .map_err(|_| "The position of the DEM can't be inverted".to_string())?;
+        let (col, row) = inverse.apply(x, y);
+        let (rows, cols) = self.dim();
+        if row < 0.0 || col < 0.0 || row >= rows as f64 || col >= cols as f64 {
+            return Err("The pour point is outside the DEM".to_string());
+        }
+        let (row, col) = (row as usize, col as usize);
+        (row.saturating_sub(SNAP_RADIUS)..(row + SNAP_RADIUS + 1).min(rows))
+            .cartesian_product(col.saturating_sub(SNAP_RADIUS)..(col + SNAP_RADIUS + 1).min(cols))
+            .filter(|cell| !self.accumulation[*cell].is_nan())
+            .max_by(|a, b| self.accumulation[*a].total_cmp(&self.accumulation[*b]))
+            .ok_or_else(|| "The pour point is in an area of no data".to_string())
+    }
+
+    /// Finds every cell that drains through the pour point, given in the projection of the DEM
+    pub fn watershed(&self, x: f64, y: f64) -> Result<Watershed, String> {
+        let dim = self.dim();
+        let pour = self.pour_cell(x, y)?;
+        let mut inside = Array2::from_elem(dim, false);
+        inside[pour] = true;
+        let mut unvisited = vec![pour];
+        while let Some(cell) = unvisited.pop() {
+            for (row, col, _) in NEIGHBOURS {
+                let Some(neighbour) = neighbour(cell, (row, col), dim) else {
+                    continue;
+                };
+                if !inside[neighbour]
+                    && downstream(neighbour, self.directions[neighbour], dim) == Some(cell)
+                {
+                    inside[neighbour] = true;
+                    unvisited.push(neighbour);
+                }
+            }
+        }
+        // Each run of cells in a row becomes a rectangle which are then merged into the outline
+        let mut runs = Vec::new();
+        for (row, cells) in inside.outer_iter().enumerate() {
+            for (is_inside, group) in &cells.iter().enumerate().group_by(|(_, inside)| **inside) {
+                if !is_inside {
+                    continue;
+                }
+                let group = group.collect_vec();
+                let (first, last) = (group[0].0, group[group.len() - 1].0);
+                runs.push(
+                    Rect::new(self.corner(row, first), self.corner(row + 1, last + 1)).to_polygon(),
+                );
+            }
+        }
+        let runs = geos::Geometry::try_from(&MultiPolygon::new(runs))
+            .map_err(|err| format!("Failed to build the watershed: {err}"))?;
+        let outline = runs
+            .unary_union()
+            .map_err(|err| format!("Failed to merge the watershed: {err}"))?;
+        Ok(Watershed {
+            outline: outline
+                .try_into()
+                .map_err(|err| format!("Failed to read the watershed: {err}"))?,
+            cells: inside.iter().filter(|inside| **inside).count(),
+            pour_point: self.centre(pour),
+        })
+    }
+}
+
+/// A stretch of stream between a source or junction and the next junction or outlet
+pub struct StreamLink {
+    /// The Strahler order
+    pub order: u32,
+    /// How many cells drain through the end of the link
+    pub upstream_cells: f64,
+    pub line: LineString,
+}
+
+pub struct Watershed {
+    pub outline: geo_types::Geometry,
+    pub cells: usize,
+    /// The centre of the cell the pour point was snapped to
+    pub pour_point: Coord,
+}
+
+/// Writes a grid to a new GeoTIFF covering the same area as the DEM
+pub fn write_grid(
+    dem: &WrappedDataset,
+    name: String,
+    data: &Array2<f64>,
+    data_type: GdalDataType,
+    no_data: f64,
+) -> Result<WrappedDataset, String> {
+    let data = data.mapv(|value| if value.is_nan() { no_data } else { value });
+    let output = create_raster_like(&dem.dataset, &name, &data, data_type, Some(no_data))?;
+    Ok(WrappedDataset::wrap_existing(output, name))
+}
+
+/// Saved as 64 bit floats because the small rises added across flats are lost at 32 bit precision
+pub fn write_filled(
+    dem: &WrappedDataset,
+    model: &FlowModel,
+    name: String,
+) -> Result<WrappedDataset, String> {
+    write_grid(dem, name, &model.filled, GdalDataType::Float64, DEM_NO_DATA)
+}
+
+pub fn write_directions(
+    dem: &WrappedDataset,
+    model: &FlowModel,
+    name: String,
+) -> Result<WrappedDataset, String> {
+    let directions = model.directions.mapv(f64::from);
+    write_grid(
+        dem,
+        name,
+        &directions,
+        GdalDataType::UInt8,
+        DIRECTION_NO_DATA as f64,
+    )
+}
+
+/// Every cell drains at least itself so 0 is free to mark no data
+pub fn write_accumulation(
+    dem: &WrappedDataset,
+    model: &FlowModel,
+    name: String,
+) -> Result<WrappedDataset, String> {
+    write_grid(dem, name, &model.accumulation, GdalDataType::UInt32, 0.0)
+}
+
+/// Creates a vector dataset with a single layer in the projection of the DEM
+fn write_features(
+    dem: &WrappedDataset,
+    path: &str,
+    ty: OGRwkbGeometryType::Type,
+    fields: &[(&str, u32)],
+    features: Vec<(gdal::vector::Geometry, Vec<GdalFieldValue>)>,
+) -> Result<WrappedDataset, String> {
+    let srs = dem.dataset.spatial_ref().ok();
+    create_vector_dataset(path, srs.as_ref(), ty, |layer| {
+        layer
+            .create_defn_fields(fields)
+            .map_err(|_| "Failed to add the fields".to_string())?;
+        let names = fields.iter().map(|(name, _)| *name).collect_vec();
+        for (geometry, values) in features {
+            layer
+                .create_feature_fields(geometry, &names, &values)
+                .map_err(|_| "Failed to add a feature".to_string())?;
+        }
+        Ok(())
+    })
+}
+
+/// Writes the stream network as lines with the Strahler order and upstream cell count of each link
+pub fn write_streams(
+    dem: &WrappedDataset,
+    links: Vec<StreamLink>,
+    path: &str,
+) -> Result<WrappedDataset, String> {
+    let features = links
+        .into_iter()
+        .map(|link| {
+            let geometry = link
+                .line
+                .to_gdal()
+                .map_err(|_| "Failed to convert a stream".to_string())?;
+            Ok((
+                geometry,
+                vec![
+                    GdalFieldValue::IntegerValue(link.order as i32),
+                    GdalFieldValue::Integer64Value(link.upstream_cells as i64),
+                ],
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    write_features(
+        dem,
+        path,
+        OGRwkbGeometryType::wkbLineString,
+        &[
+            ("order", FieldType::OFTInteger as u32),
+            ("up_cells", FieldType::OFTInteger64 as u32),
+        ],
+        features,
+    )
+}
+
+/// Writes the outline of a watershed along with how many cells it covers and where its pour point ended up
+pub fn write_watershed(
+    dem: &WrappedDataset,
+    watershed: Watershed,
+    path: &str,
+) -> Result<WrappedDataset, String> {
+    let geometry = watershed
+        .outline
+        .to_gdal()
+        .map_err(|_| "Failed to convert the watershed".to_string())?;
+    write_features(
+        dem,
+        path,
+        OGRwkbGeometryType::wkbUnknown,
+        &[
+            ("cells", FieldType::OFTInteger64 as u32),
+            ("pour_x", FieldType::OFTReal as u32),
+            ("pour_y", FieldType::OFTReal as u32),
+        ],
+        vec![(
+            geometry,
+            vec![
+                GdalFieldValue::Integer64Value(watershed.cells as i64),
+                GdalFieldValue::RealValue(watershed.pour_point.x),
+                GdalFieldValue::RealValue(watershed.pour_point.y),
+            ],
+        )],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::*;
+
+    const NAN: f64 = f64::NAN;
+
+    fn model(dem: Array2<f64>) -> FlowModel {
+        FlowModel::from_dem(&dem, (1.0, 1.0), [0.0, 1.0, 0.0, 0.0, 0.0, -1.0])
+    }
+
+    #[test]
+    fn pits_are_filled_and_drain() {
+        let dem = array![[10.0, 10.0, 10.0], [10.0, 5.0, 10.0], [10.0, 8.0, 10.0]];
+        let model = model(dem.clone());
+        // The pit is raised just above its outlet and nothing else changes
+        assert!(model.filled[(1, 1)] > 8.0 && model.filled[(1, 1)] < 8.001);
+        for (cell, elevation) in dem.indexed_iter() {
+            if cell != (1, 1) {
+                assert_eq!(model.filled[cell], *elevation);
+            }
+        }
+        assert_eq!(model.directions[(1, 1)], 4);
+        // Everything drains through the pit and out of the gap in its rim
+        assert_eq!(model.accumulation[(1, 1)], 6.0);
+        assert_eq!(model.accumulation[(2, 1)], 9.0);
+    }
+
+    #[test]
+    fn flats_drain_towards_their_outlet() {
+        let filled = fill_depressions(&array![
+            [10.0, 10.0, 10.0, 10.0],
+            [10.0, 5.0, 5.0, 10.0],
+            [10.0, 5.0, 5.0, 1.0],
+            [10.0, 10.0, 10.0, 10.0]
+        ]);
+        let directions = flow_directions(&filled, (1.0, 1.0));
+        for cell in [(1, 1), (1, 2), (2, 1), (2, 2)] {
+            assert_ne!(directions[cell], OUTLET, "{cell:?} has nowhere to drain");
+        }
+    }
+
+    #[test]
+    fn directions_on_a_plane() {
+        let east = Array2::from_shape_fn((3, 3), |(_, col)| 10.0 - col as f64);
+        assert_eq!(flow_directions(&east, (1.0, 1.0))[(1, 1)], 1);
+        let south = Array2::from_shape_fn((3, 3), |(row, _)| 10.0 - row as f64);
+        assert_eq!(flow_directions(&south, (1.0, 1.0))[(1, 1)], 4);
+        let south_east = Array2::from_shape_fn((3, 3), |(row, col)| 10.0 - (row + col) as f64);
+        assert_eq!(flow_directions(&south_east, (1.0, 1.0))[(1, 1)], 2);
+        // Narrow cells make the step east steeper than the diagonal
+        assert_eq!(flow_directions(&south_east, (0.5, 1.0))[(1, 1)], 1);
+        // The lowest corner has nowhere lower to go
+        assert_eq!(flow_directions(&south_east, (1.0, 1.0))[(2, 2)], OUTLET);
+    }
+
+    #[test]
+    fn every_cell_is_counted_once_at_an_outlet() {
+        let model = model(array![
+            [9.0, 8.0, 9.0, 9.0, 7.0],
+            [8.0, 4.0, 6.0, NAN, 6.0],
+            [9.0, 5.0, 2.0, 7.0, 8.0],
+            [9.0, 6.0, 3.0, 5.0, 9.0]
+        ]);
+        let cells = model.filled.iter().filter(|value| !value.is_nan()).count();
+        let at_outlets = model
+            .accumulation
+            .indexed_iter()
+            .filter(|(cell, _)| model.directions[*cell] == OUTLET)
+            .map(|(_, accumulation)| accumulation)
+            .sum::<f64>();
+        assert_eq!(at_outlets, cells as f64);
+        assert!(model.accumulation[(1, 3)].is_nan());
+        assert_eq!(model.directions[(1, 3)], DIRECTION_NO_DATA);
+        assert!(model
+            .accumulation
+            .iter()
+            .filter(|value| !value.is_nan())
+            .all(|value| *value >= 1.0));
+    }
+
+    #[test]
+    fn streams_joining_increase_the_order() {
+        // Two sources flow into a junction that continues south to the bottom edge
+        let model = model(array![
+            [4.0, NAN, 4.0],
+            [NAN, 3.0, NAN],
+            [NAN, 2.0, NAN],
+            [NAN, 1.0, NAN]
+        ]);
+        let links = model.stream_links(1.0);
+        let summary = links
+            .iter()
+            .map(|link| (link.order, link.upstream_cells, link.line.0.len()))
+            .collect_vec();
+        assert_eq!(summary, vec![(1, 1.0, 2), (1, 1.0, 2), (2, 5.0, 3)]);
+        // The trunk starts at the junction
+        assert_eq!(links[2].line.0[0], Coord { x: 1.5, y: -1.5 });
+    }
+}
@@ -1,5 +1,5 @@
 use gdal::vector::Feature;
-use geo::{Closest, ClosestPoint, GeodesicDistance};
+use geo::{Closest, ClosestPoint, GeodesicBearing, GeodesicDistance};
 use geo_types::{Geometry as GeoGeometry, Point};
 use serde::{Deserialize, Serialize};
 
@@ -32,6 +32,20 @@ impl LocalFeatureInfo {
         })
     }
 
+    /// The distance in metres and bearing in degrees clockwise from north of the closest part of the feature
+    /// The bearing is 0 when the point is inside the feature
+    /// Note, this operates on WGS84 coordinates
+    pub fn distance_and_bearing(&self, point: &Point) -> Option<(f64, f64)> {
+        match self.geometry.closest_point(point) {
+            Closest::SinglePoint(p) => Some((
+                p.geodesic_distance(point),
+                point.geodesic_bearing(p).rem_euclid(360.0),
+            )),
+            Closest::Intersection(_) => Some((0.0, 0.0)),
+            Closest::Indeterminate => None,
+        }
+    }
+
     pub fn get_name(&self) -> Option<String> {
         Some(
             self.fields
@@ -1,11 +1,15 @@
+pub mod contour;
 mod dataset;
 mod field_schema;
 mod fields;
+pub mod hydrology;
 mod layer;
 mod local_feature;
 pub mod processing;
 mod raster;
 pub mod vector;
+pub mod viewshed;
+pub mod zonal;
 
 pub use dataset::*;
 pub use field_schema::*;
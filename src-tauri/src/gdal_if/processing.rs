@@ -1,12 +1,25 @@
 use gdal::{
     errors::GdalError,
     raster::processing::dem::{
-        aspect as gdal_aspect, roughness as gdal_roughness, slope as gdal_slope, AspectOptions,
-        RoughnessOptions, SlopeOptions,
+        aspect as gdal_aspect, hillshade as gdal_hillshade, roughness as gdal_roughness,
+        slope as gdal_slope, terrain_ruggedness_index as gdal_tri,
+        topographic_position_index as gdal_tpi, AspectOptions, DemSlopeAlg, HillshadeOptions,
+        RoughnessOptions, SlopeOptions, TpiOptions, TriOptions,
     },
+    spatial_ref::SpatialRef,
+    Dataset, DriverManager, GeoTransformEx,
 };
 
-use super::WrappedDataset;
+use std::{collections::HashMap, ffi::CString, num::NonZeroUsize, path::Path, ptr};
+
+use gdal::raster::GdalDataType;
+use itertools::Itertools;
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use crate::calculator::{self, BandRef};
+
+use super::{create_raster_like, read_raster_data, smallest_data_type, WrappedDataset};
 macro_rules! dem_proccessing_function {
     ($gdal_name:ident, $app_name:ident, $options_name:ident) => {
         pub fn $app_name(
@@ -15,7 +28,9 @@ macro_rules! dem_proccessing_function {
             options: &$options_name,
         ) -> Result<WrappedDataset, GdalError> {
             Ok(WrappedDataset::wrap_existing(
-                $gdal_name(&dataset.dataset, &name, options)?,
+                with_equal_axes(&dataset.dataset, |dataset| {
+                    $gdal_name(dataset, &name, options)
+                })?,
                 name,
             ))
         }
@@ -27,3 +42,501 @@ dem_proccessing_function!(gdal_roughness, roughness, RoughnessOptions);
 dem_proccessing_function!(gdal_slope, slope, SlopeOptions);
 
 dem_proccessing_function!(gdal_aspect, aspect, AspectOptions);
+
+dem_proccessing_function!(gdal_hillshade, hillshade, HillshadeOptions);
+
+dem_proccessing_function!(gdal_tpi, topographic_position_index, TpiOptions);
+
+dem_proccessing_function!(gdal_tri, terrain_ruggedness_index, TriOptions);
+
+/// Horizontal metres in a degree, the scale gdaldem suggests for DEMs in latitude and longitude
+const METRES_PER_DEGREE: f64 = 111_120.0;
+
+/// The ratio of horizontal units to elevation units needed for a DEM
+/// Only DEMs in a geographic coordinate system need one, their elevations are assumed to be in metres
+/// This is the scale along the y axis, gdaldem only takes one so the x axis is corrected by [`with_equal_axes`]
+pub fn geographic_scale(dataset: &Dataset) -> Option<f64> {
+    dataset
+        .spatial_ref()
+        .ok()
+        .is_some_and(|srs| srs.is_geographic())
+        .then_some(METRES_PER_DEGREE)
+}
+
+/// Metres per unit along the x and y axes of a projection at the given latitude
+/// Degrees of longitude get shorter towards the poles so geographic coordinates need a smaller x scale away from the equator
+pub fn srs_scale(srs: &SpatialRef, latitude: f64) -> Option<(f64, f64)> {
+    srs.is_geographic().then(|| {
+        (
+            METRES_PER_DEGREE * latitude.to_radians().cos(),
+            METRES_PER_DEGREE,
+        )
+    })
+}
+
+/// The scale of each axis of a dataset at its centre, see [`srs_scale`]
+pub(super) fn dataset_scale(dataset: &Dataset) -> Option<(f64, f64)> {
+    let srs = dataset.spatial_ref().ok()?;
+    let geo_transform = dataset.geo_transform().ok()?;
+    let (cols, rows) = dataset.raster_size();
+    let (_, latitude) = geo_transform.apply(cols as f64 / 2.0, rows as f64 / 2.0);
+    srs_scale(&srs, latitude)
+}
+
+/// Runs a gdaldem process with degrees of longitude and latitude covering the same distance
+/// Geographic DEMs are processed from a virtual copy with pixels narrowed by the cosine of the latitude at the centre
+/// The virtual copy only references the original so the DEM isn't read into memory
+/// The output is then moved back over the area of the original
+fn with_equal_axes(
+    dataset: &Dataset,
+    process: impl FnOnce(&Dataset) -> Result<Dataset, GdalError>,
+) -> Result<Dataset, GdalError> {
+    let (Some((x_scale, y_scale)), Ok(geo_transform)) =
+        (dataset_scale(dataset), dataset.geo_transform())
+    else {
+        return process(dataset);
+    };
+    // A VRT with no name stays in memory without copying the pixels of the DEM
+    let driver = DriverManager::get_driver_by_name("VRT")?;
+    let name = CString::default();
+    let copy = unsafe {
+        gdal_sys::GDALCreateCopy(
+            driver.c_driver(),
+            name.as_ptr(),
+            dataset.c_dataset(),
+            0,
+            ptr::null_mut(),
+            None,
+            ptr::null_mut(),
+        )
+    };
+    if copy.is_null() {
+        return Err(GdalError::NullPointer {
+            method_name: "GDALCreateCopy",
+            msg: "Failed to create a virtual copy of the DEM".to_string(),
+        });
+    }
+    let mut copy = unsafe { Dataset::from_c_dataset(copy) };
+    let ratio = x_scale / y_scale;
+    let mut narrowed = geo_transform;
+    narrowed[1] *= ratio;
+    narrowed[2] *= ratio;
+    copy.set_geo_transform(&narrowed)?;
+    let mut output = process(&copy)?;
+    output.set_geo_transform(&geo_transform)?;
+    Ok(output)
+}
+
+fn input_band(band: Option<usize>) -> Result<Option<NonZeroUsize>, String> {
+    band.map(|band| NonZeroUsize::new(band).ok_or_else(|| "Bands start at 1".to_string()))
+        .transpose()
+}
+
+/// How gdaldem estimates the gradient at each pixel
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+pub enum SlopeAlgorithm {
+    /// Better suited to rough terrain
+    #[default]
+    Horn,
+    /// Better suited to smooth terrain
+    ZevenbergenThorne,
+}
+
+impl From<SlopeAlgorithm> for DemSlopeAlg {
+    fn from(algorithm: SlopeAlgorithm) -> Self {
+        match algorithm {
+            SlopeAlgorithm::Horn => DemSlopeAlg::Horn,
+            SlopeAlgorithm::ZevenbergenThorne => DemSlopeAlg::ZevenbergenThorne,
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+#[serde(default)]
+pub struct SlopeSettings {
+    /// Defaults to the first band
+    pub band: Option<usize>,
+    pub algorithm: SlopeAlgorithm,
+    /// Percent rise instead of degrees
+    pub percentage: bool,
+    /// Overrides the scale detected from the spatial reference
+    pub scale: Option<f64>,
+    /// Interpolate the edges instead of leaving them as no data
+    pub compute_edges: bool,
+}
+
+impl SlopeSettings {
+    pub fn to_options(&self, dataset: &Dataset) -> Result<SlopeOptions, String> {
+        let mut options = SlopeOptions::new();
+        options
+            .with_algorithm(self.algorithm.into())
+            .with_percentage_results(self.percentage)
+            .with_compute_edges(self.compute_edges);
+        if let Some(band) = input_band(self.band)? {
+            options.with_input_band(band);
+        }
+        if let Some(scale) = self.scale.or_else(|| geographic_scale(dataset)) {
+            options.with_scale(scale);
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+#[serde(default)]
+pub struct AspectSettings {
+    /// Defaults to the first band
+    pub band: Option<usize>,
+    pub algorithm: SlopeAlgorithm,
+    /// Give flat areas an aspect of 0 instead of no data
+    pub zero_for_flat: bool,
+    /// Interpolate the edges instead of leaving them as no data
+    pub compute_edges: bool,
+}
+
+impl AspectSettings {
+    pub fn to_options(&self, _dataset: &Dataset) -> Result<AspectOptions, String> {
+        let mut options = AspectOptions::new();
+        options
+            .with_algorithm(self.algorithm.into())
+            .with_zero_for_flat(self.zero_for_flat)
+            .with_compute_edges(self.compute_edges);
+        if let Some(band) = input_band(self.band)? {
+            options.with_input_band(band);
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+#[serde(default)]
+pub struct RoughnessSettings {
+    /// Defaults to the first band
+    pub band: Option<usize>,
+    /// Interpolate the edges instead of leaving them as no data
+    pub compute_edges: bool,
+}
+
+impl RoughnessSettings {
+    pub fn to_options(&self, _dataset: &Dataset) -> Result<RoughnessOptions, String> {
+        let mut options = RoughnessOptions::new();
+        options.with_compute_edges(self.compute_edges);
+        if let Some(band) = input_band(self.band)? {
+            options.with_input_band(band);
+        }
+        Ok(options)
+    }
+}
+
+/// The no data value gdaldem gives its outputs
+pub(super) const DEM_NO_DATA: f64 = -9999.0;
+
+/// Which direction curvature is measured in
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+pub enum CurvatureKind {
+    /// Along the direction of the slope, negative values are convex and accelerate flow
+    Profile,
+    /// Across the direction of the slope, positive values are convex and spread flow out
+    Plan,
+}
+
+/// Calculates the curvature of the centre of a 3x3 window of elevations
+/// Uses the polynomial fit of Zevenbergen and Thorne, multiplied by 100 like most GIS software
+/// Flat areas have no direction so are given a curvature of 0
+fn window_curvature(z: [[f64; 3]; 3], (dx, dy): (f64, f64), kind: CurvatureKind) -> f64 {
+    let d = ((z[1][0] + z[1][2]) / 2.0 - z[1][1]) / (dx * dx);
+    let e = ((z[0][1] + z[2][1]) / 2.0 - z[1][1]) / (dy * dy);
+    let f = (-z[0][0] + z[0][2] + z[2][0] - z[2][2]) / (4.0 * dx * dy);
+    let g = (z[1][2] - z[1][0]) / (2.0 * dx);
+    let h = (z[0][1] - z[2][1]) / (2.0 * dy);
+    let gradient = g * g + h * h;
+    if gradient == 0.0 {
+        return 0.0;
+    }
+    let curvature = match kind {
+        CurvatureKind::Profile => -2.0 * (d * g * g + e * h * h + f * g * h) / gradient,
+        CurvatureKind::Plan => 2.0 * (d * h * h + e * g * g - f * g * h) / gradient,
+    };
+    curvature * 100.0
+}
+
+/// Calculates the profile or plan curvature of the first band of a DEM into a new GeoTIFF
+/// Pixels on the edge or next to no data have no full window so are left as no data
+/// Cell sizes in degrees are converted to metres
+pub fn curvature(
+    dataset: &WrappedDataset,
+    name: String,
+    kind: CurvatureKind,
+) -> Result<WrappedDataset, String> {
+    let band = dataset
+        .dataset
+        .rasterband(1)
+        .map_err(|_| "Failed to read band 1".to_string())?;
+    let no_data = band.no_data_value();
+    let data = read_raster_data(&band);
+    let geo_transform = dataset
+        .dataset
+        .geo_transform()
+        .map_err(|_| "The dataset has no geotransform".to_string())?;
+    let (x_scale, y_scale) = dataset_scale(&dataset.dataset).unwrap_or((1.0, 1.0));
+    let cell_size = (
+        geo_transform[1].abs() * x_scale,
+        geo_transform[5].abs() * y_scale,
+    );
+    let is_no_data = |value: f64| value.is_nan() || no_data == Some(value);
+    let (rows, cols) = data.dim();
+    let output = Array2::from_shape_fn((rows, cols), |(row, col)| {
+        if row == 0 || col == 0 || row + 1 == rows || col + 1 == cols {
+            return DEM_NO_DATA;
+        }
+        let mut window = [[0.0; 3]; 3];
+        for (y, window_row) in window.iter_mut().enumerate() {
+            for (x, value) in window_row.iter_mut().enumerate() {
+                *value = data[(row + y - 1, col + x - 1)];
+                if is_no_data(*value) {
+                    return DEM_NO_DATA;
+                }
+            }
+        }
+        window_curvature(window, cell_size, kind)
+    });
+    let output = create_raster_like(
+        &dataset.dataset,
+        &name,
+        &output,
+        GdalDataType::Float32,
+        Some(DEM_NO_DATA),
+    )?;
+    Ok(WrappedDataset::wrap_existing(output, name))
+}
+
+/// Which ends of a classification's range count as part of it
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+pub enum ClassBounds {
+    /// min <= value <= max
+    #[default]
+    Inclusive,
+    /// min < value < max
+    Exclusive,
+    /// min <= value < max
+    IncludeMin,
+    /// min < value <= max
+    IncludeMax,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+pub struct Classification {
+    pub min: f64,
+    pub max: f64,
+    pub target: f64,
+    #[serde(default)]
+    pub bounds: ClassBounds,
+}
+
+impl Classification {
+    fn contains(&self, value: f64) -> bool {
+        let above_min = match self.bounds {
+            ClassBounds::Inclusive | ClassBounds::IncludeMin => value >= self.min,
+            ClassBounds::Exclusive | ClassBounds::IncludeMax => value > self.min,
+        };
+        let below_max = match self.bounds {
+            ClassBounds::Inclusive | ClassBounds::IncludeMax => value <= self.max,
+            ClassBounds::Exclusive | ClassBounds::IncludeMin => value < self.max,
+        };
+        above_min && below_max
+    }
+}
+
+/// Replaces each value with the target of the first classification that contains it
+/// Values no classification contains are set to the default or no data if there isn't one
+pub fn classify_data(
+    data: &Array2<f64>,
+    no_data: Option<f64>,
+    classifications: &[Classification],
+    default: Option<f64>,
+    output_no_data: f64,
+) -> Array2<f64> {
+    data.mapv(|value| {
+        if value.is_nan() || no_data == Some(value) {
+            return output_no_data;
+        }
+        classifications
+            .iter()
+            .find(|classification| classification.contains(value))
+            .map(|classification| classification.target)
+            .or(default)
+            .unwrap_or(output_no_data)
+    })
+}
+
+/// Picks a no data value for a classified raster that fits the smallest data type holding every target
+/// The no data value of the source is kept if it fits, otherwise the highest or lowest value of the type is used
+fn classified_no_data(targets: &[f64], source: Option<f64>) -> f64 {
+    let data_type = smallest_data_type(targets.iter().copied());
+    let (min, max) = match data_type {
+        GdalDataType::UInt8 => (u8::MIN as f64, u8::MAX as f64),
+        GdalDataType::UInt16 => (u16::MIN as f64, u16::MAX as f64),
+        GdalDataType::Int16 => (i16::MIN as f64, i16::MAX as f64),
+        GdalDataType::UInt32 => (u32::MIN as f64, u32::MAX as f64),
+        GdalDataType::Int32 => (i32::MIN as f64, i32::MAX as f64),
+        GdalDataType::Float32 => (f32::MIN as f64, f32::MAX as f64),
+        _ => (f64::MIN, f64::MAX),
+    };
+    source
+        .filter(|source| smallest_data_type(targets.iter().copied().chain([*source])) == data_type)
+        .into_iter()
+        .chain([max, min])
+        .find(|value| !targets.contains(value))
+        // Every value of the type is a target so go one past the largest and use a bigger type
+        .unwrap_or_else(|| targets.iter().copied().fold(f64::MIN, f64::max).floor() + 1.0)
+}
+
+/// Classifies a band of a dataset into a new GeoTIFF
+/// The output uses the smallest data type that holds every target and a no data value that fits it
+pub fn classify(
+    dataset: &WrappedDataset,
+    band_index: usize,
+    name: String,
+    classifications: &[Classification],
+    default: Option<f64>,
+) -> Result<WrappedDataset, String> {
+    if classifications.is_empty() {
+        return Err("No classifications given".to_string());
+    }
+    if let Some(classification) = classifications
+        .iter()
+        .find(|classification| classification.min > classification.max)
+    {
+        return Err(format!(
+            "The minimum {} is larger than the maximum {}",
+            classification.min, classification.max
+        ));
+    }
+    let band = dataset
+        .dataset
+        .rasterband(band_index)
+        .map_err(|_| format!("Failed to read band {band_index}"))?;
+    let no_data = band.no_data_value();
+    let data = read_raster_data(&band);
+    let targets = classifications
+        .iter()
+        .map(|classification| classification.target)
+        .chain(default)
+        .collect_vec();
+    let needs_no_data =
+        no_data.is_some() || default.is_none() || data.iter().any(|value| value.is_nan());
+    let output_no_data = classified_no_data(&targets, no_data);
+    let classified = classify_data(&data, no_data, classifications, default, output_no_data);
+    let data_type = smallest_data_type(
+        targets
+            .into_iter()
+            .chain(needs_no_data.then_some(output_no_data)),
+    );
+    let output = create_raster_like(
+        &dataset.dataset,
+        &name,
+        &classified,
+        data_type,
+        needs_no_data.then_some(output_no_data),
+    )?;
+    Ok(WrappedDataset::wrap_existing(output, name))
+}
+
+/// Whether a name used in a calculator expression refers to a dataset
+/// Names are matched against the file name without its extension, ignoring case and treating anything other than letters and numbers as an underscore
+fn is_dataset_named(dataset: &WrappedDataset, name: &str) -> bool {
+    let Some(stem) = Path::new(&dataset.file_name).file_stem() else {
+        return false;
+    };
+    let identifier = stem
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    identifier.eq_ignore_ascii_case(name)
+}
+
+/// Evaluates a map algebra expression over bands of the open datasets and writes the result to a new GeoTIFF
+/// Every band used must be the same size and cover the same area
+pub fn calculate(
+    datasets: &[&WrappedDataset],
+    current: Option<usize>,
+    expression: &str,
+    name: String,
+) -> Result<WrappedDataset, String> {
+    let expr = calculator::parse(expression)?;
+    let mut grids = HashMap::<BandRef, Array2<f64>>::new();
+    let mut like = None;
+    for band_ref in expr.bands() {
+        let dataset = match &band_ref.dataset {
+            None => current.and_then(|current| datasets.get(current)).copied(),
+            Some(name) => datasets
+                .iter()
+                .copied()
+                .find(|dataset| is_dataset_named(dataset, name)),
+        }
+        .ok_or_else(|| match &band_ref.dataset {
+            None => "No dataset selected".to_string(),
+            Some(name) => format!("No open dataset is named {name}"),
+        })?;
+        let band = dataset
+            .dataset
+            .rasterband(band_ref.band)
+            .map_err(|_| format!("{band_ref} doesn't exist"))?;
+        let geo_transform = dataset.dataset.geo_transform().ok();
+        match like {
+            None => like = Some((dataset, band.size(), geo_transform)),
+            Some((first, size, first_transform)) => {
+                if band.size() != size {
+                    return Err(format!(
+                        "{band_ref} is {} by {} pixels but {} is {} by {}",
+                        band.size().0,
+                        band.size().1,
+                        first.file_name,
+                        size.0,
+                        size.1
+                    ));
+                }
+                if geo_transform != first_transform {
+                    return Err(format!(
+                        "{band_ref} doesn't cover the same area as {}, reproject or resample it first",
+                        first.file_name
+                    ));
+                }
+            }
+        }
+        let no_data = band.no_data_value();
+        let data = read_raster_data(&band).mapv_into(|value| {
+            if no_data == Some(value) {
+                f64::NAN
+            } else {
+                value
+            }
+        });
+        grids.insert(band_ref.clone(), data);
+    }
+    let result = expr.evaluate(&|band_ref| grids.get(band_ref))?;
+    let Some((like, _, _)) = like else {
+        return Err("The expression doesn't use any bands".to_string());
+    };
+
+    let values = result.iter().copied().filter(|value| !value.is_nan());
+    let has_no_data = result.iter().any(|value| value.is_nan());
+    let (data_type, no_data) = match smallest_data_type(values.clone()) {
+        data_type @ (GdalDataType::Float32 | GdalDataType::Float64) => {
+            (data_type, has_no_data.then_some(f64::NAN))
+        }
+        // Integers can't store NaN so no data needs a value that isn't used
+        _ if has_no_data => {
+            let no_data = values.clone().reduce(f64::max).map_or(0.0, |max| max + 1.0);
+            (smallest_data_type(values.chain([no_data])), Some(no_data))
+        }
+        data_type => (data_type, None),
+    };
+    let result = match no_data {
+        Some(no_data) => result.mapv_into(|value| if value.is_nan() { no_data } else { value }),
+        None => result,
+    };
+    let output = create_raster_like(&like.dataset, &name, &result, data_type, no_data)?;
+    Ok(WrappedDataset::wrap_existing(output, name))
+}
@@ -1,13 +1,22 @@
+use std::path::Path;
+
 use gdal::{
-    raster::{GdalDataType, RasterBand, ResampleAlg},
-    GeoTransform, GeoTransformEx,
+    raster::{Buffer, GdalDataType, GdalType, RasterBand, ResampleAlg},
+    spatial_ref::SpatialRef,
+    vector::{Layer, LayerOptions, OGRwkbGeometryType},
+    Dataset, DriverManager, GeoTransform, GeoTransformEx,
 };
 use geo_types::Point;
 use itertools::Itertools;
 use ndarray::Array2;
+use num_traits::AsPrimitive;
 use proj::{Coord, Transform};
 use serde::{Deserialize, Serialize};
 
+use crate::stats::BandStatistics;
+
+use super::{get_driver_for_file, processing::srs_scale, WrappedDataset};
+
 pub struct WrappedRasterBand<'a> {
     pub band: RasterBand<'a>,
     pub geo_transform: Option<GeoTransform>,
@@ -30,6 +39,46 @@ impl<'a> WrappedRasterBand<'a> {
     pub fn band(&self) -> &RasterBand<'a> {
         &self.band
     }
+
+    /// Works out the statistics of every pixel that isn't no data
+    /// The area is in square metres for geographic bands, measured at their centre latitude
+    /// Otherwise it is in the square units of the projection, or of pixels when the band has no position information
+    pub fn statistics(&self, percentiles: &[f64]) -> Result<BandStatistics, String> {
+        let no_data = self.no_data_value();
+        let data = read_raster_data(&self.band);
+        let total = data.len();
+        let values = data
+            .into_iter()
+            .filter(|value| !value.is_nan() && no_data != Some(*value))
+            .collect_vec();
+        let no_data_count = total - values.len();
+        let srs = self
+            .srs
+            .as_deref()
+            .and_then(|wkt| SpatialRef::from_wkt(wkt).ok());
+        let (cols, rows) = self.band.size();
+        let scale = match (&srs, self.geo_transform) {
+            (Some(srs), Some(gt)) => {
+                let (_, latitude) = gt.apply(cols as f64 / 2.0, rows as f64 / 2.0);
+                srs_scale(srs, latitude)
+            }
+            _ => None,
+        };
+        let (x_scale, y_scale) = scale.unwrap_or((1.0, 1.0));
+        let pixel_area = self.geo_transform.map_or(1.0, |gt| {
+            (gt[1] * gt[5] - gt[2] * gt[4]).abs() * x_scale * y_scale
+        });
+        let area_unit = match srs {
+            _ if self.geo_transform.is_none() => "square pixel".to_string(),
+            _ if scale.is_some() => "square metre".to_string(),
+            Some(srs) => srs
+                .linear_units_name()
+                .map(|unit| format!("square {unit}"))
+                .unwrap_or_else(|_| "square unit".to_string()),
+            None => "square unit".to_string(),
+        };
+        BandStatistics::new(values, no_data_count, pixel_area, area_unit, percentiles)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -283,3 +332,142 @@ pub fn read_raster_data_enum_as(
         _ => None,
     }
 }
+
+/// Finds the smallest data type that can hold every value exactly
+pub fn smallest_data_type(values: impl IntoIterator<Item = f64>) -> GdalDataType {
+    let mut integral = true;
+    let mut single = true;
+    let (mut min, mut max) = (0.0_f64, 0.0_f64);
+    for value in values {
+        integral &= value.fract() == 0.0;
+        single &= value.is_nan() || (value as f32) as f64 == value;
+        min = min.min(value);
+        max = max.max(value);
+    }
+    if !integral {
+        return if single {
+            GdalDataType::Float32
+        } else {
+            GdalDataType::Float64
+        };
+    }
+    if min >= u8::MIN as f64 && max <= u8::MAX as f64 {
+        GdalDataType::UInt8
+    } else if min >= u16::MIN as f64 && max <= u16::MAX as f64 {
+        GdalDataType::UInt16
+    } else if min >= i16::MIN as f64 && max <= i16::MAX as f64 {
+        GdalDataType::Int16
+    } else if min >= u32::MIN as f64 && max <= u32::MAX as f64 {
+        GdalDataType::UInt32
+    } else if min >= i32::MIN as f64 && max <= i32::MAX as f64 {
+        GdalDataType::Int32
+    } else {
+        GdalDataType::Float64
+    }
+}
+
+/// Writes values to a new single band GeoTIFF covering the same area as an existing dataset
+/// The values are converted to the given data type so it should be able to hold all of them
+pub fn create_raster_like(
+    like: &Dataset,
+    name: &str,
+    data: &Array2<f64>,
+    data_type: GdalDataType,
+    no_data: Option<f64>,
+) -> Result<Dataset, String> {
+    match data_type {
+        GdalDataType::UInt8 => create_raster_as::<u8>(like, name, data, no_data),
+        GdalDataType::Int8 => create_raster_as::<i8>(like, name, data, no_data),
+        GdalDataType::UInt16 => create_raster_as::<u16>(like, name, data, no_data),
+        GdalDataType::Int16 => create_raster_as::<i16>(like, name, data, no_data),
+        GdalDataType::UInt32 => create_raster_as::<u32>(like, name, data, no_data),
+        GdalDataType::Int32 => create_raster_as::<i32>(like, name, data, no_data),
+        GdalDataType::Float32 => create_raster_as::<f32>(like, name, data, no_data),
+        _ => create_raster_as::<f64>(like, name, data, no_data),
+    }
+}
+
+fn create_raster_as<T>(
+    like: &Dataset,
+    name: &str,
+    data: &Array2<f64>,
+    no_data: Option<f64>,
+) -> Result<Dataset, String>
+where
+    T: GdalType + Copy + 'static,
+    f64: AsPrimitive<T>,
+{
+    let driver = DriverManager::get_driver_by_name("GTiff")
+        .map_err(|_| "Failed to get the GeoTIFF driver".to_string())?;
+    let (rows, cols) = data.dim();
+    let mut dataset = driver
+        .create_with_band_type::<T, _>(name, cols, rows, 1)
+        .map_err(|err| format!("Failed to create raster {name}: {err}"))?;
+    if let Ok(geo_transform) = like.geo_transform() {
+        dataset
+            .set_geo_transform(&geo_transform)
+            .map_err(|_| "Failed to set the position of the new raster".to_string())?;
+    }
+    if let Ok(srs) = like.spatial_ref() {
+        dataset
+            .set_spatial_ref(&srs)
+            .map_err(|_| "Failed to set the projection of the new raster".to_string())?;
+    }
+    {
+        let mut band = dataset
+            .rasterband(1)
+            .map_err(|_| "Failed to get the band of the new raster".to_string())?;
+        if no_data.is_some() {
+            band.set_no_data_value(no_data)
+                .map_err(|_| "Failed to set the no data value of the new raster".to_string())?;
+        }
+        let mut buffer = Buffer::new((cols, rows), data.iter().map(|value| value.as_()).collect());
+        band.write((0, 0), (cols, rows), &mut buffer)
+            .map_err(|err| format!("Failed to write raster {name}: {err}"))?;
+    }
+    dataset
+        .flush_cache()
+        .map_err(|_| "Failed to save the new raster to disc".to_string())?;
+    Ok(dataset)
+}
+
+/// Creates a new vector dataset with a single layer named after the file
+/// The fields and features are added to the layer by the given function before the dataset is saved
+pub fn create_vector_dataset<F>(
+    path: &str,
+    srs: Option<&SpatialRef>,
+    ty: OGRwkbGeometryType::Type,
+    fill: F,
+) -> Result<WrappedDataset, String>
+where
+    F: FnOnce(&mut Layer) -> Result<(), String>,
+{
+    let driver = get_driver_for_file(Path::new(path))
+        .ok_or_else(|| format!("Could not find a driver for {path}"))?;
+    let mut dataset: Dataset = driver
+        .create_vector_only(path)
+        .map_err(|_| format!("Failed to create dataset {path}"))?;
+    {
+        let name = Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut layer = dataset
+            .create_layer(LayerOptions {
+                name: &name,
+                srs,
+                ty,
+                options: None,
+            })
+            .map_err(|_| "Failed to create layer".to_string())?;
+        fill(&mut layer)?;
+    }
+    dataset
+        .flush_cache()
+        .map_err(|_| "Failed to save the new dataset to disc".to_string())?;
+    Ok(WrappedDataset {
+        file_name: path.to_string(),
+        dataset,
+        editable: true,
+    })
+}
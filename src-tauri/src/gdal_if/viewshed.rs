@@ -0,0 +1,206 @@
+//! Works out which cells of a DEM can be seen from an observer
+
+use gdal::{spatial_ref::SpatialRef, GeoTransformEx};
+use itertools::Itertools;
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use super::{processing::srs_scale, read_raster_data, WrappedRasterBand};
+
+const EARTH_RADIUS: f64 = 6_371_000.0;
+/// How much the atmosphere bends light back towards the ground, the usual coefficient for visible light
+const REFRACTION: f64 = 0.13;
+
+const HIDDEN: f64 = 0.0;
+const VISIBLE: f64 = 1.0;
+pub const VIEWSHED_NO_DATA: f64 = 255.0;
+
+/// Compass directions clockwise from north, each covering 45 degrees
+const DIRECTIONS: [&str; 8] = [
+    "north",
+    "north east",
+    "east",
+    "south east",
+    "south",
+    "south west",
+    "west",
+    "north west",
+];
+
+/// Where the observer stands
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+#[serde(tag = "type", content = "value")]
+pub enum ObserverPosition {
+    /// The column and row of a pixel
+    Pixel { col: usize, row: usize },
+    /// Coordinates in the projection of the DEM
+    Map { x: f64, y: f64 },
+}
+
+pub struct Viewshed {
+    /// 1 where the target can be seen, 0 where it can't and no data beyond the maximum distance
+    pub visibility: Array2<f64>,
+    pub summary: String,
+}
+
+fn format_distance(metres: f64) -> String {
+    if metres >= 1000.0 {
+        format!("{:.1} km", metres / 1000.0)
+    } else {
+        format!("{:.0} m", metres)
+    }
+}
+
+fn format_area(square_metres: f64) -> String {
+    if square_metres >= 1_000_000.0 {
+        format!("{:.2} square km", square_metres / 1_000_000.0)
+    } else {
+        format!("{:.0} square metres", square_metres)
+    }
+}
+
+/// Works out which cells a target standing target_height above the ground could be seen from by an observer standing observer_height above the ground
+/// Sight lines are traced out to every cell on the edge of the area checked and corrected for the curvature of the earth and refraction
+/// Distances and heights are in metres, DEMs in latitude and longitude are scaled to match
+pub fn viewshed(
+    band: &WrappedRasterBand,
+    observer: ObserverPosition,
+    observer_height: f64,
+    target_height: f64,
+    max_distance: Option<f64>,
+) -> Result<Viewshed, String> {
+    let geo_transform = band
+        .geo_transform
+        .ok_or_else(|| "The DEM has no position information".to_string())?;
+    let no_data = band.no_data_value();
+    let dem = read_raster_data(band.band()).mapv(|value| {
+        if no_data == Some(value) {
+            f64::NAN
+        } else {
+            value
+        }
+    });
+    let (rows, cols) = dem.dim();
+    let (row, col) = match observer {
+        ObserverPosition::Pixel { col, row } => (row, col),
+        ObserverPosition::Map { x, y } => {
+            let inverse = geo_transform
+                .invert()
+                .map_err(|_| "The position of the DEM can't be inverted".to_string())?;
+            let (col, row) = inverse.apply(x, y);
+            if col < 0.0 || row < 0.0 {
+                return Err("The observer is outside the DEM".to_string());
+            }
+            (row as usize, col as usize)
+        }
+    };
+    if row >= rows || col >= cols {
+        return Err("The observer is outside the DEM".to_string());
+    }
+    let ground = dem[(row, col)];
+    if ground.is_nan() {
+        return Err("The observer is standing on no data".to_string());
+    }
+    let eye = ground + observer_height;
+    // Degrees of longitude are measured at the observer's latitude
+    let (_, latitude) = geo_transform.apply(col as f64 + 0.5, row as f64 + 0.5);
+    let (x_scale, y_scale) = band
+        .srs
+        .as_deref()
+        .and_then(|wkt| SpatialRef::from_wkt(wkt).ok())
+        .and_then(|srs| srs_scale(&srs, latitude))
+        .unwrap_or((1.0, 1.0));
+    // Metres east and north of the observer
+    let offset = |(cell_row, cell_col): (usize, usize)| {
+        let rows_away = cell_row as f64 - row as f64;
+        let cols_away = cell_col as f64 - col as f64;
+        (
+            (cols_away * geo_transform[1] + rows_away * geo_transform[2]) * x_scale,
+            (cols_away * geo_transform[4] + rows_away * geo_transform[5]) * y_scale,
+        )
+    };
+
+    // Only cells within the maximum distance need a sight line
+    let (min_row, max_row, min_col, max_col) = match max_distance {
+        Some(distance) => {
+            let row_reach = (distance / (geo_transform[5].abs() * y_scale)).ceil() as usize;
+            let col_reach = (distance / (geo_transform[1].abs() * x_scale)).ceil() as usize;
+            (
+                row.saturating_sub(row_reach),
+                (row + row_reach).min(rows - 1),
+                col.saturating_sub(col_reach),
+                (col + col_reach).min(cols - 1),
+            )
+        }
+        None => (0, rows - 1, 0, cols - 1),
+    };
+    let edge = (min_col..=max_col)
+        .flat_map(|edge_col| [(min_row, edge_col), (max_row, edge_col)])
+        .chain((min_row..=max_row).flat_map(|edge_row| [(edge_row, min_col), (edge_row, max_col)]))
+        .unique();
+
+    let mut visibility = Array2::from_elem((rows, cols), VIEWSHED_NO_DATA);
+    visibility[(row, col)] = VISIBLE;
+    let mut furthest = [None::<f64>; 8];
+    for (edge_row, edge_col) in edge {
+        let rows_away = edge_row as isize - row as isize;
+        let cols_away = edge_col as isize - col as isize;
+        let steps = rows_away.abs().max(cols_away.abs());
+        // The steepest the ground has been seen along the sight line so far
+        let mut horizon = f64::NEG_INFINITY;
+        for step in 1..=steps {
+            let cell = (
+                (row as f64 + (rows_away * step) as f64 / steps as f64).round() as usize,
+                (col as f64 + (cols_away * step) as f64 / steps as f64).round() as usize,
+            );
+            let (east, north) = offset(cell);
+            let distance = east.hypot(north);
+            if max_distance.is_some_and(|max_distance| distance > max_distance) {
+                break;
+            }
+            let elevation = dem[cell];
+            if elevation.is_nan() {
+                continue;
+            }
+            let drop = distance * distance * (1.0 - REFRACTION) / (2.0 * EARTH_RADIUS);
+            let ground_angle = (elevation - drop - eye) / distance;
+            let target_angle = (elevation + target_height - drop - eye) / distance;
+            if target_angle >= horizon {
+                visibility[cell] = VISIBLE;
+                let bearing = east.atan2(north).to_degrees().rem_euclid(360.0);
+                let direction = (bearing / 45.0).round() as usize % 8;
+                furthest[direction] =
+                    Some(furthest[direction].map_or(distance, |d| d.max(distance)));
+            } else if visibility[cell] == VIEWSHED_NO_DATA {
+                visibility[cell] = HIDDEN;
+            }
+            horizon = horizon.max(ground_angle);
+        }
+    }
+
+    let visible = visibility.iter().filter(|value| **value == VISIBLE).count();
+    let checked = visibility
+        .iter()
+        .filter(|value| **value != VIEWSHED_NO_DATA)
+        .count();
+    let cell_area = (geo_transform[1] * geo_transform[5]).abs() * x_scale * y_scale;
+    let mut summary = format!(
+        "{} is visible, {:.1}% of the area checked",
+        format_area(visible as f64 * cell_area),
+        visible as f64 / checked as f64 * 100.0
+    );
+    let directions = DIRECTIONS
+        .iter()
+        .zip(furthest)
+        .filter_map(|(name, distance)| Some((name, distance?)))
+        .sorted_by(|a, b| b.1.total_cmp(&a.1))
+        .map(|(name, distance)| format!("{name} {}", format_distance(distance)))
+        .join(", ");
+    if !directions.is_empty() {
+        summary += &format!(". Furthest visibility: {directions}");
+    }
+    Ok(Viewshed {
+        visibility,
+        summary,
+    })
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+
+use gdal::{
+    raster::RasterBand,
+    vector::{Feature, FieldValue as GdalFieldValue, LayerAccess, OGRwkbGeometryType},
+    GeoTransform, GeoTransformEx,
+};
+use geo::{BoundingRect, Contains};
+use geo_types::Point;
+use itertools::Itertools;
+use proj::Transform;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::GeoGeometry;
+
+use super::{
+    create_vector_dataset, read_raster_data_enum_as, FieldType, FieldValue, WrappedDataset,
+};
+
+/// Rasters with more distinct values than this are treated as continuous so don't get a percentage for each class
+const MAX_CLASSES: usize = 32;
+/// The longest field name shapefiles can hold
+const MAX_FIELD_NAME_LEN: usize = 10;
+/// Added to the start of the statistics fields when the layer already has fields with the same names
+const STATS_PREFIX: &str = "z_";
+
+/// Where the statistics of each zone are written
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, specta::Type)]
+#[serde(tag = "type", content = "value")]
+pub enum ZonalOutput {
+    /// A copy of the polygon layer with the statistics added as fields, added to the open datasets
+    Layer(String),
+    /// A CSV file with a row for each feature containing its fields followed by its statistics
+    Csv(String),
+}
+
+/// The statistics of the raster pixels whose centres fall inside a single polygon
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ZonalStats {
+    pub count: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// The population standard deviation
+    pub std: Option<f64>,
+    /// The most common value, the lowest is taken when there's a tie
+    pub majority: Option<f64>,
+    /// How many pixels have each value
+    counts: Vec<(f64, usize)>,
+}
+
+impl ZonalStats {
+    fn from_values(values: &[f64]) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / count as f64;
+        let counts = values
+            .iter()
+            .counts_by(|value| value.to_bits())
+            .into_iter()
+            .map(|(bits, count)| (f64::from_bits(bits), count))
+            .sorted_by(|a, b| a.0.total_cmp(&b.0))
+            .collect_vec();
+        let majority = counts
+            .iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.total_cmp(&a.0)))
+            .map(|(value, _)| *value);
+        Self {
+            count,
+            min: values.iter().copied().reduce(f64::min),
+            max: values.iter().copied().reduce(f64::max),
+            mean: Some(mean),
+            std: Some(variance.sqrt()),
+            majority,
+            counts,
+        }
+    }
+
+    /// The percentage of the zone's pixels that have the given value
+    pub fn percentage(&self, class: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let count = self
+            .counts
+            .iter()
+            .find(|(value, _)| *value == class)
+            .map_or(0, |(_, count)| *count);
+        count as f64 / self.count as f64 * 100.0
+    }
+}
+
+/// The statistics of every feature of a layer in the order the features are read
+pub struct ZonalTable {
+    pub stats: Vec<ZonalStats>,
+    /// The values that get a percentage column, empty when the raster looks continuous
+    pub classes: Vec<f64>,
+}
+
+impl ZonalTable {
+    fn field_names(&self, prefix: &str) -> Vec<String> {
+        ["count", "min", "max", "mean", "std", "majority"]
+            .into_iter()
+            .map(|name| format!("{prefix}{name}"))
+            .chain(
+                self.classes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, class)| class_field_name(prefix, index, *class)),
+            )
+            .collect()
+    }
+
+    /// Names the statistics so they don't clash with the existing fields, ignoring case like shapefiles do
+    /// The names are prefixed if any clash and it is an error if they still do
+    fn unique_field_names(&self, existing: &[String]) -> Result<Vec<String>, String> {
+        let clash = |names: &[String]| {
+            names
+                .iter()
+                .find(|name| {
+                    existing
+                        .iter()
+                        .any(|field| field.eq_ignore_ascii_case(name))
+                })
+                .cloned()
+        };
+        let names = self.field_names("");
+        if clash(&names).is_none() {
+            return Ok(names);
+        }
+        let names = self.field_names(STATS_PREFIX);
+        match clash(&names) {
+            None => Ok(names),
+            Some(name) => Err(format!(
+                "The layer already has a field named {name}, rename it before adding the statistics"
+            )),
+        }
+    }
+}
+
+fn class_field_name(prefix: &str, index: usize, class: f64) -> String {
+    // Field names can't contain a minus sign in most formats
+    let name = format!("{prefix}pct_{}", class.to_string().replace('-', "m"));
+    if name.len() <= MAX_FIELD_NAME_LEN {
+        name
+    } else {
+        // Values too long to fit are named by their position among the classes instead
+        format!("{prefix}pct_n{}", index + 1)
+    }
+}
+
+/// Reads the values of the pixels whose centres are inside the zone, skipping no data
+fn zone_values(
+    band: &RasterBand,
+    geo_transform: &GeoTransform,
+    inverse: &GeoTransform,
+    zone: &GeoGeometry,
+) -> Vec<f64> {
+    let Some(bounds) = zone.bounding_rect() else {
+        return Vec::new();
+    };
+    let (width, height) = band.size();
+    let corners = [
+        (bounds.min().x, bounds.min().y),
+        (bounds.min().x, bounds.max().y),
+        (bounds.max().x, bounds.min().y),
+        (bounds.max().x, bounds.max().y),
+    ]
+    .map(|(x, y)| inverse.apply(x, y));
+    let cols = corners.iter().map(|corner| corner.0).minmax().into_option();
+    let rows = corners.iter().map(|corner| corner.1).minmax().into_option();
+    let (Some((min_col, max_col)), Some((min_row, max_row))) = (cols, rows) else {
+        return Vec::new();
+    };
+    let min_col = (min_col.floor().max(0.0) as usize).min(width);
+    let max_col = (max_col.ceil().max(0.0) as usize).min(width);
+    let min_row = (min_row.floor().max(0.0) as usize).min(height);
+    let max_row = (max_row.ceil().max(0.0) as usize).min(height);
+    if min_col >= max_col || min_row >= max_row {
+        return Vec::new();
+    }
+    let size = (max_col - min_col, max_row - min_row);
+    let Some(data) =
+        read_raster_data_enum_as(band, (min_col as isize, min_row as isize), size, size, None)
+    else {
+        return Vec::new();
+    };
+    let no_data = band.no_data_value();
+    data.into_f64_vec()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, value)| !value.is_nan() && no_data != Some(*value))
+        .filter(|(index, _)| {
+            let col = min_col + index % size.0;
+            let row = min_row + index / size.0;
+            let (x, y) = geo_transform.apply(col as f64 + 0.5, row as f64 + 0.5);
+            zone.contains(&Point::new(x, y))
+        })
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/// Works out the statistics of a raster band within each polygon of a layer
+/// The polygons are reprojected into the projection of the raster when they differ
+pub fn zonal_statistics(
+    zones: &WrappedDataset,
+    layer_index: usize,
+    raster: &WrappedDataset,
+    band_index: usize,
+) -> Result<ZonalTable, String> {
+    let mut layer = zones
+        .dataset
+        .layer(layer_index)
+        .map_err(|_| format!("Failed to read layer {layer_index} of {}", zones.file_name))?;
+    let band = raster
+        .dataset
+        .rasterband(band_index)
+        .map_err(|_| format!("Failed to read band {band_index} of {}", raster.file_name))?;
+    let geo_transform = raster
+        .dataset
+        .geo_transform()
+        .map_err(|_| format!("{} has no position information", raster.file_name))?;
+    let inverse = geo_transform
+        .invert()
+        .map_err(|_| format!("The position of {} can't be inverted", raster.file_name))?;
+    let projections = match (layer.spatial_ref(), raster.dataset.spatial_ref()) {
+        (Some(layer_srs), Ok(raster_srs)) if layer_srs != raster_srs => Some((
+            layer_srs
+                .to_wkt()
+                .map_err(|_| "Failed to read the layer's projection")?,
+            raster_srs
+                .to_wkt()
+                .map_err(|_| "Failed to read the raster's projection")?,
+        )),
+        _ => None,
+    };
+
+    let stats = layer
+        .features()
+        .map(|feature| {
+            let Some(zone) = feature
+                .geometry()
+                .and_then(|geometry| geometry.to_geo().ok())
+            else {
+                return Ok(ZonalStats::default());
+            };
+            let zone = match &projections {
+                Some((from, to)) => zone
+                    .transformed_crs_to_crs(from, to)
+                    .map_err(|_| format!("Failed to reproject feature {:?}", feature.fid()))?,
+                None => zone,
+            };
+            let values = zone_values(&band, &geo_transform, &inverse, &zone);
+            Ok(ZonalStats::from_values(&values))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let classes = stats
+        .iter()
+        .flat_map(|stats| stats.counts.iter().map(|(value, _)| *value))
+        .unique_by(|value| value.to_bits())
+        .sorted_by(f64::total_cmp)
+        .collect_vec();
+    let categorical =
+        classes.len() <= MAX_CLASSES && classes.iter().all(|value| value.fract() == 0.0);
+    Ok(ZonalTable {
+        stats,
+        classes: if categorical { classes } else { Vec::new() },
+    })
+}
+
+fn stats_values(table: &ZonalTable, stats: &ZonalStats) -> Vec<Option<f64>> {
+    [
+        Some(stats.count as f64),
+        stats.min,
+        stats.max,
+        stats.mean,
+        stats.std,
+        stats.majority,
+    ]
+    .into_iter()
+    .chain(
+        table
+            .classes
+            .iter()
+            .map(|class| Some(stats.percentage(*class))),
+    )
+    .collect()
+}
+
+fn feature_fields(feature: &Feature) -> Vec<(String, GdalFieldValue)> {
+    feature
+        .fields()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect()
+}
+
+/// Writes the statistics to a CSV file with the fid and fields of each feature before its statistics
+pub fn write_zonal_csv(
+    zones: &WrappedDataset,
+    layer_index: usize,
+    table: &ZonalTable,
+    path: &str,
+) -> Result<(), String> {
+    let mut layer = zones
+        .dataset
+        .layer(layer_index)
+        .map_err(|_| format!("Failed to read layer {layer_index} of {}", zones.file_name))?;
+    let field_names = layer
+        .defn()
+        .fields()
+        .map(|field| field.name())
+        .collect_vec();
+    let existing = ["fid".to_string()]
+        .into_iter()
+        .chain(field_names.iter().cloned())
+        .collect_vec();
+    let stats_names = table.unique_field_names(&existing)?;
+    let mut output =
+        csv::Writer::from_path(path).map_err(|err| format!("Failed to create {path}: {err}"))?;
+    output
+        .write_record(existing.into_iter().chain(stats_names))
+        .map_err(|err| err.to_string())?;
+    for (feature, stats) in layer.features().zip(&table.stats) {
+        let mut fields = feature_fields(&feature)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let record = [feature.fid().map(|fid| fid.to_string()).unwrap_or_default()]
+            .into_iter()
+            .chain(field_names.iter().map(|name| {
+                fields
+                    .remove(name)
+                    .map(|value| FieldValue::from(value).to_string())
+                    .unwrap_or_default()
+            }))
+            .chain(
+                stats_values(table, stats)
+                    .into_iter()
+                    .map(|value| value.map(|value| value.to_string()).unwrap_or_default()),
+            );
+        output.write_record(record).map_err(|err| err.to_string())?;
+    }
+    output.flush().map_err(|err| err.to_string())
+}
+
+/// Copies the layer into a new dataset with the statistics added as fields
+/// The new layer is named after the file
+/// Features without a geometry have nothing to measure so aren't copied
+pub fn write_zonal_layer(
+    zones: &WrappedDataset,
+    layer_index: usize,
+    table: &ZonalTable,
+    path: &str,
+) -> Result<WrappedDataset, String> {
+    let mut layer = zones
+        .dataset
+        .layer(layer_index)
+        .map_err(|_| format!("Failed to read layer {layer_index} of {}", zones.file_name))?;
+    let existing = layer
+        .defn()
+        .fields()
+        .map(|field| field.name())
+        .collect_vec();
+    let stats_names = table.unique_field_names(&existing)?;
+    let srs = layer.spatial_ref();
+    create_vector_dataset(
+        path,
+        srs.as_ref(),
+        OGRwkbGeometryType::wkbUnknown,
+        |output| {
+            let fields = layer
+                .defn()
+                .fields()
+                .map(|field| (field.name(), field.field_type()))
+                .chain(stats_names.iter().enumerate().map(|(index, name)| {
+                    let field_type = if index == 0 {
+                        FieldType::OFTInteger64
+                    } else {
+                        FieldType::OFTReal
+                    };
+                    (name.clone(), field_type as u32)
+                }))
+                .collect_vec();
+            output
+                .create_defn_fields(
+                    &fields
+                        .iter()
+                        .map(|(name, field_type)| (name.as_str(), *field_type))
+                        .collect_vec(),
+                )
+                .map_err(|_| "Failed to add the statistics fields".to_string())?;
+            for (feature, stats) in layer.features().zip(&table.stats) {
+                let Some(geometry) = feature.geometry().cloned() else {
+                    continue;
+                };
+                let (names, values): (Vec<_>, Vec<_>) = feature_fields(&feature)
+                    .into_iter()
+                    .chain(
+                        stats_names
+                            .iter()
+                            .zip(stats_values(table, stats))
+                            .enumerate()
+                            .filter_map(|(index, (name, value))| {
+                                let value = match index {
+                                    0 => GdalFieldValue::Integer64Value(value? as i64),
+                                    _ => GdalFieldValue::RealValue(value?),
+                                };
+                                Some((name.clone(), value))
+                            }),
+                    )
+                    .unzip();
+                output
+                    .create_feature_fields(
+                        geometry,
+                        &names.iter().map(String::as_str).collect_vec(),
+                        &values,
+                    )
+                    .map_err(|_| format!("Failed to copy feature {:?}", feature.fid()))?;
+            }
+            Ok(())
+        },
+    )
+}
@@ -43,6 +43,13 @@ impl AppData {
         }
     }
 
+    /// Adds a dataset that was created outside of the collection, like the output of a tool
+    pub fn add_dataset(&mut self, dataset: WrappedDataset) -> &mut StatefulDataset {
+        self.shared
+            .datasets
+            .add(StatefulDataset::new(dataset, &self.settings))
+    }
+
     pub fn create_from_current_dataset<E, F>(
         &mut self,
         f: F,
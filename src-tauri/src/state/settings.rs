@@ -6,7 +6,9 @@ use tauri::{
     Runtime,
 };
 
-use crate::audio::{graph::RasterGraphSettings, histogram::HistogramSettings};
+use crate::audio::{
+    graph::RasterGraphSettings, histogram::HistogramSettings, indicators::Indicators, Waveform,
+};
 
 use super::gis::raster::RenderMethod;
 
@@ -105,6 +107,19 @@ impl AudioSettings {
     pub fn graph(&self) -> &RasterGraphSettings {
         &self.graph
     }
+
+    /// The waveform chosen for graphs, also used for histograms so every sonification sounds alike
+    pub fn wave(&self) -> Waveform {
+        self.graph.wave
+    }
+
+    pub fn indicators(&self) -> Indicators {
+        Indicators {
+            volume: self.volume,
+            no_data: self.no_data_value_sound.clone(),
+            border: self.border_sound.clone(),
+        }
+    }
 }
 
 impl Default for AudioSettings {
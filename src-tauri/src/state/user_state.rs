@@ -39,6 +39,10 @@ impl UserState {
             .collect_vec()
     }
 
+    pub fn get_all_vectors(&mut self) -> Vec<StatefulVectorLayer> {
+        self.datasets.get_vectors().collect_vec()
+    }
+
     pub fn create_from_current_dataset<E, F>(
         &mut self,
         f: F,
@@ -1,16 +1,20 @@
-use num_traits::Num;
+use std::path::Path;
+
+use num_traits::{Num, NumCast};
+use serde::{Deserialize, Serialize};
+use statrs::statistics::Statistics;
 
 pub trait Mean<A = Self>: Sized {
     fn mean<I: Iterator<Item = A>>(iter: I) -> Self;
 }
 
-impl<'a, T: 'a + Num + Copy + From<usize>> Mean<&'a T> for T {
+impl<'a, T: 'a + Num + Copy + NumCast> Mean<&'a T> for T {
     fn mean<I>(iter: I) -> Self
     where
         I: Iterator<Item = &'a T>,
     {
         let (sum, len) = iter.fold((T::zero(), 0_usize), |(sum, len), c| (sum + *c, len + 1));
-        sum / T::from(len)
+        sum / T::from(len).expect("Count too large for the type being averaged")
     }
 }
 
@@ -25,3 +29,226 @@ pub trait IteratorMyExt: Iterator {
 }
 
 impl<T: Iterator> IteratorMyExt for T {}
+
+/// The value below which the given percentage of sorted values fall, interpolating between the two closest ranks
+fn quantile(sorted: &[f64], percentile: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let rank = percentile / 100.0 * last as f64;
+    let lower = sorted[rank.floor() as usize];
+    let upper = sorted[rank.ceil() as usize];
+    Some(lower + (upper - lower) * rank.fract())
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, specta::Type)]
+pub struct Percentile {
+    /// Between 0 and 100
+    pub percentile: f64,
+    pub value: f64,
+}
+
+/// Summary statistics of the valid pixels of a raster band
+/// Statistics that can't be worked out from the number of valid pixels are left empty
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, specta::Type)]
+pub struct BandStatistics {
+    pub valid_count: usize,
+    pub no_data_count: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// The sample standard deviation
+    pub std_dev: Option<f64>,
+    pub median: Option<f64>,
+    pub percentiles: Vec<Percentile>,
+    /// The adjusted sample skewness, positive when there is a long tail of high values
+    pub skewness: Option<f64>,
+    /// The area covered by valid pixels
+    pub area: f64,
+    /// Such as square metre or square pixel
+    pub area_unit: String,
+}
+
+impl BandStatistics {
+    /// Works out the statistics of the valid values of a band
+    /// Percentiles are given between 0 and 100 and are found by interpolating between the closest values
+    pub fn new(
+        values: Vec<f64>,
+        no_data_count: usize,
+        pixel_area: f64,
+        area_unit: String,
+        percentiles: &[f64],
+    ) -> Result<Self, String> {
+        if let Some(percentile) = percentiles
+            .iter()
+            .find(|percentile| !(0.0..=100.0).contains(*percentile))
+        {
+            return Err(format!("Percentile {percentile} is not between 0 and 100"));
+        }
+        let valid_count = values.len();
+        let non_empty = |value: f64| (valid_count > 0).then_some(value);
+        let mean = non_empty(<f64 as Mean<_>>::mean(values.iter()));
+        let std_dev = (valid_count > 1).then(|| values.iter().std_dev());
+        let skewness = match (mean, std_dev) {
+            (Some(mean), Some(std_dev)) if valid_count > 2 && std_dev > 0.0 => {
+                let n = valid_count as f64;
+                let cubed = values
+                    .iter()
+                    .map(|value| ((value - mean) / std_dev).powi(3))
+                    .sum::<f64>();
+                Some(n / ((n - 1.0) * (n - 2.0)) * cubed)
+            }
+            _ => None,
+        };
+        let mut sorted = values;
+        sorted.sort_by(f64::total_cmp);
+        let min = sorted.first().copied();
+        let max = sorted.last().copied();
+        let median = quantile(&sorted, 50.0);
+        let percentiles = percentiles
+            .iter()
+            .filter_map(|percentile| {
+                Some(Percentile {
+                    percentile: *percentile,
+                    value: quantile(&sorted, *percentile)?,
+                })
+            })
+            .collect();
+        Ok(Self {
+            valid_count,
+            no_data_count,
+            min,
+            max,
+            mean,
+            std_dev,
+            median,
+            percentiles,
+            skewness,
+            area: valid_count as f64 * pixel_area,
+            area_unit,
+        })
+    }
+
+    /// Each statistic as a name and value in the order they should be read
+    pub fn rows(&self) -> Vec<(String, String)> {
+        let optional =
+            |value: Option<f64>| value.map(|value| value.to_string()).unwrap_or_default();
+        let mut rows = vec![
+            ("valid pixels".to_string(), self.valid_count.to_string()),
+            ("no data pixels".to_string(), self.no_data_count.to_string()),
+            ("minimum".to_string(), optional(self.min)),
+            ("maximum".to_string(), optional(self.max)),
+            ("mean".to_string(), optional(self.mean)),
+            ("standard deviation".to_string(), optional(self.std_dev)),
+            ("median".to_string(), optional(self.median)),
+        ];
+        rows.extend(self.percentiles.iter().map(|percentile| {
+            (
+                format!("percentile {}", percentile.percentile),
+                percentile.value.to_string(),
+            )
+        }));
+        rows.push(("skewness".to_string(), optional(self.skewness)));
+        rows.push((format!("area ({})", self.area_unit), self.area.to_string()));
+        rows
+    }
+
+    /// Writes a CSV file with a row for each statistic
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let mut output = csv::Writer::from_path(path).map_err(|err| err.to_string())?;
+        output
+            .write_record(["statistic", "value"])
+            .map_err(|err| err.to_string())?;
+        for (name, value) in self.rows() {
+            output
+                .write_record([name, value])
+                .map_err(|err| err.to_string())?;
+        }
+        output.flush().map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statistics(values: Vec<f64>, percentiles: &[f64]) -> BandStatistics {
+        BandStatistics::new(values, 2, 4.0, "square metre".to_string(), percentiles).unwrap()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("The statistic should have a value");
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{actual} isn't {expected}"
+        );
+    }
+
+    #[test]
+    fn empty_bands_have_no_statistics() {
+        let stats = statistics(Vec::new(), &[50.0]);
+        assert_eq!(stats.valid_count, 0);
+        assert_eq!(stats.no_data_count, 2);
+        assert_eq!(
+            [
+                stats.min,
+                stats.max,
+                stats.mean,
+                stats.std_dev,
+                stats.median,
+                stats.skewness
+            ],
+            [None; 6]
+        );
+        assert!(stats.percentiles.is_empty());
+        assert_eq!(stats.area, 0.0);
+    }
+
+    #[test]
+    fn a_single_value_has_no_spread() {
+        let stats = statistics(vec![7.0], &[]);
+        assert_eq!(stats.min, Some(7.0));
+        assert_eq!(stats.max, Some(7.0));
+        assert_eq!(stats.mean, Some(7.0));
+        assert_eq!(stats.median, Some(7.0));
+        assert_eq!(stats.std_dev, None);
+        assert_eq!(stats.skewness, None);
+        assert_eq!(stats.area, 4.0);
+    }
+
+    #[test]
+    fn median_and_percentiles() {
+        let values = vec![7.0, 3.0, 10.0, 1.0, 5.0, 9.0, 2.0, 8.0, 4.0, 6.0];
+        let stats = statistics(values, &[0.0, 25.0, 90.0, 100.0]);
+        assert_close(stats.median, 5.5);
+        let percentiles = stats
+            .percentiles
+            .iter()
+            .map(|percentile| percentile.value)
+            .collect::<Vec<_>>();
+        assert_eq!(percentiles.len(), 4);
+        assert_close(Some(percentiles[0]), 1.0);
+        assert_close(Some(percentiles[1]), 3.25);
+        assert_close(Some(percentiles[2]), 9.1);
+        assert_close(Some(percentiles[3]), 10.0);
+        assert_close(stats.mean, 5.5);
+        // The sample standard deviation of 1 to 10
+        assert_close(stats.std_dev, (55.0_f64 / 6.0).sqrt());
+        assert_close(stats.skewness, 0.0);
+    }
+
+    #[test]
+    fn skewness_follows_the_long_tail() {
+        let high_tail = statistics(vec![1.0, 1.0, 2.0, 10.0], &[]);
+        assert!(high_tail.skewness.unwrap() > 0.0);
+        let low_tail = statistics(vec![-10.0, -2.0, -1.0, -1.0], &[]);
+        assert!(low_tail.skewness.unwrap() < 0.0);
+    }
+
+    #[test]
+    fn percentiles_outside_the_range_are_rejected() {
+        let result = BandStatistics::new(vec![1.0], 0, 1.0, String::new(), &[101.0]);
+        assert_eq!(
+            result,
+            Err("Percentile 101 is not between 0 and 100".to_string())
+        );
+    }
+}